@@ -0,0 +1,543 @@
+use std::ffi::{OsStr, OsString};
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{BufReader, Cursor};
+use std::path::Path;
+
+use image::codecs::gif::{GifDecoder, GifEncoder, Repeat};
+use image::imageops::{self, FilterType};
+use image::{AnimationDecoder, Frame, Rgba, RgbaImage};
+
+use crate::colour::Colour;
+use crate::errors::ThumbnailError;
+use crate::ffmpeg::FfmpegConfig;
+use crate::video_encoding::VideoEncoding;
+
+/// Where an encoder reads the original GIF or video from.
+#[derive(Debug, PartialEq)]
+pub enum AnimationInput<'a> {
+    Path(&'a Path),
+
+    /// The contents of the file, e.g. if we read it from stdin
+    Bytes(&'a [u8]),
+}
+
+impl AnimationInput<'_> {
+    /// The path to the original file, for errors, or an empty path if
+    /// we only have it in memory.
+    pub fn path(&self) -> &Path {
+        match self {
+            AnimationInput::Path(path) => path,
+            AnimationInput::Bytes(_) => Path::new(""),
+        }
+    }
+}
+
+/// Where an encoder writes the thumbnail.
+#[derive(Debug, PartialEq)]
+pub enum AnimationOutput<'a> {
+    Path(&'a Path),
+
+    /// Return the thumbnail in `EncodedAnimation::bytes`
+    Memory,
+}
+
+/// A request to turn an animated image or a video into a looping
+/// thumbnail.
+#[derive(Debug, PartialEq)]
+pub struct AnimationJob<'a> {
+    /// The original GIF or video
+    pub input: AnimationInput<'a>,
+
+    /// Where the thumbnail should be written
+    pub output: AnimationOutput<'a>,
+
+    /// The dimensions of the thumbnail, as computed from the
+    /// `TargetDimension` the user asked for
+    pub width: u32,
+    pub height: u32,
+
+    /// For videos, the timestamp (in seconds) where the thumbnail
+    /// should start
+    pub start: Option<f64>,
+
+    /// For videos, how many seconds of the video to include
+    pub duration: Option<f64>,
+
+    /// The colour to use if the encoder needs to fill in any pixels
+    pub background: Option<Colour>,
+}
+
+/// Information about a looping thumbnail created by an encoder.
+#[derive(Debug, PartialEq)]
+pub struct EncodedAnimation {
+    /// The dimensions of the thumbnail, which may not be exactly the
+    /// dimensions that were requested -- e.g. ffmpeg needs them to be even.
+    pub width: u32,
+    pub height: u32,
+
+    /// The arguments passed to ffmpeg, if the encoder used it
+    pub ffmpeg_args: Option<Vec<String>>,
+
+    /// The encoded thumbnail, if the job asked for it in memory
+    pub bytes: Option<Vec<u8>>,
+}
+
+/// Something that can create looping thumbnails.
+///
+/// We choose an encoder with `ThumbnailOptions::animated_encoder`, which
+/// means tests (or anybody else) can swap ffmpeg out for something else.
+pub trait AnimatedEncoder: fmt::Debug {
+    /// The extension of files created by this encoder, e.g. `mp4`.
+    fn extension(&self) -> &str;
+
+    /// Create the thumbnail described by `job`.
+    fn encode(&self, job: &AnimationJob) -> Result<EncodedAnimation, ThumbnailError>;
+
+    /// The dimensions of the thumbnail this encoder would create for a
+    /// `width`×`height` job.  These are the dimensions in `EncodedAnimation`,
+    /// which may be slightly different, but we sometimes need to know
+    /// them before we start encoding, e.g. to name the thumbnail.
+    fn output_dimensions(&self, width: u32, height: u32) -> (u32, u32) {
+        (width, height)
+    }
+
+    /// A description of the settings which affect the thumbnails this
+    /// encoder creates.  If two encoders have the same settings, they
+    /// create identical thumbnails, so we can reuse them.
+    ///
+    /// This goes into the names of content-addressed thumbnails, so it
+    /// has to stay the same between releases.
+    fn settings(&self) -> serde_json::Value;
+}
+
+/// Create looping videos with ffmpeg.
+///
+/// This is typically much smaller and more space-efficient than
+/// creating a resized GIF.
+#[derive(Debug, Default)]
+pub struct FfmpegEncoder {
+    pub encoding: VideoEncoding,
+    pub ffmpeg: FfmpegConfig,
+}
+
+impl AnimatedEncoder for FfmpegEncoder {
+    fn extension(&self) -> &str {
+        self.encoding.extension()
+    }
+
+    fn output_dimensions(&self, width: u32, height: u32) -> (u32, u32) {
+        // The background colour doesn't affect the dimensions.
+        let (_, dimensions) = self.encoding.scale_filter(width, height, Colour::BLACK);
+        dimensions
+    }
+
+    fn settings(&self) -> serde_json::Value {
+        // Where we find ffmpeg and how long we wait for it don't
+        // change the thumbnail.
+        serde_json::json!({ "encoder": "ffmpeg", "encoding": self.encoding })
+    }
+
+    fn encode(&self, job: &AnimationJob) -> Result<EncodedAnimation, ThumbnailError> {
+        // If we have the input in memory, we pass it to ffmpeg on stdin.
+        let (input, input_bytes) = match job.input {
+            AnimationInput::Path(path) => (path.as_os_str(), None),
+            AnimationInput::Bytes(bytes) => (OsStr::new("pipe:0"), Some(bytes)),
+        };
+
+        // If the width/height aren't even, ffmpeg doesn't create the MP4,
+        // instead failing with the error:
+        //
+        //     width not divisible by 2
+        //
+        // so we adjust the dimensions according to the user's policy.
+        let background = job.background.unwrap_or(Colour::BLACK);
+        let (filter, (width, height)) = self
+            .encoding
+            .scale_filter(job.width, job.height, background);
+
+        let mut args: Vec<OsString> = vec![];
+        if let Some(start) = job.start {
+            args.extend(["-ss".into(), start.to_string().into()]);
+        }
+        if let Some(duration) = job.duration {
+            args.extend(["-t".into(), duration.to_string().into()]);
+        }
+        args.extend(["-i".into(), input.to_os_string()]);
+        args.extend(self.encoding.ffmpeg_args().into_iter().map(OsString::from));
+        args.extend([
+            "-filter_complex".into(),
+            composite_filter(&filter, background).into(),
+        ]);
+
+        match job.output {
+            AnimationOutput::Path(path) => args.push(path.into()),
+            AnimationOutput::Memory => {
+                args.extend(self.encoding.pipe_args().into_iter().map(OsString::from));
+                args.push("pipe:1".into());
+            }
+        }
+
+        let stdout = self
+            .ffmpeg
+            .run_ffmpeg_with_input(job.input.path(), &args, input_bytes)?;
+
+        Ok(EncodedAnimation {
+            width,
+            height,
+            // The arguments are only for display, so it's fine if a
+            // path which isn't UTF-8 gets mangled here.
+            ffmpeg_args: Some(
+                args.iter()
+                    .map(|a| a.to_string_lossy().into_owned())
+                    .collect(),
+            ),
+            bytes: match job.output {
+                AnimationOutput::Path(_) => None,
+                AnimationOutput::Memory => Some(stdout),
+            },
+        })
+    }
+}
+
+/// Build an ffmpeg filter graph which resizes the input with `scale_filter`,
+/// then places it on top of a solid background.
+///
+/// The videos we create don't have an alpha channel, so if we passed
+/// ffmpeg a GIF with transparent pixels, they'd come out as black or
+/// whatever garbage was left in the frame buffer.
+///
+/// We paint the background over a copy of each frame, rather than using
+/// a separate `color` source, so the GIF's own timing goes through the
+/// filter graph.  A `color` source runs at 25 fps, and `overlay` would
+/// resample the GIF to match it.
+fn composite_filter(scale_filter: &str, background: Colour) -> String {
+    format!(
+        "[0:v]{},format=yuva444p,split[a][b];[a]drawbox=c={}:t=fill:replace=1[bg];[bg][b]overlay",
+        scale_filter,
+        background.to_ffmpeg(),
+    )
+}
+
+/// Place an image on top of a solid background, so every pixel is opaque.
+fn composite(img: &mut RgbaImage, background: Colour) {
+    for pixel in img.pixels_mut() {
+        let [r, g, b, a] = pixel.0;
+        let blend = |fg: u8, bg: u8| {
+            ((fg as u32 * a as u32 + bg as u32 * (255 - a as u32) + 127) / 255) as u8
+        };
+
+        *pixel = Rgba([
+            blend(r, background.red),
+            blend(g, background.green),
+            blend(b, background.blue),
+            255,
+        ]);
+    }
+}
+
+/// Create resized, looping GIFs without any external tools.
+///
+/// These are bigger than the videos created by ffmpeg, but this encoder
+/// works anywhere.  It can only read GIFs, not videos.
+///
+/// GIFs support transparency, so we only fill in transparent pixels
+/// if the job has a background colour.
+#[derive(Debug, Default)]
+pub struct GifThumbnailEncoder;
+
+impl AnimatedEncoder for GifThumbnailEncoder {
+    fn extension(&self) -> &str {
+        "gif"
+    }
+
+    fn settings(&self) -> serde_json::Value {
+        serde_json::json!({ "encoder": "gif" })
+    }
+
+    fn encode(&self, job: &AnimationJob) -> Result<EncodedAnimation, ThumbnailError> {
+        let input_path = job.input.path();
+
+        if job.start.is_some() || job.duration.is_some() {
+            return Err(ThumbnailError::EncodeFailed {
+                path: input_path.to_path_buf(),
+                message: "the gif encoder can't create previews of videos".to_string(),
+            });
+        }
+
+        let frames = match job.input {
+            AnimationInput::Path(path) => {
+                let file = File::open(path).map_err(|e| ThumbnailError::io(path, e))?;
+                GifDecoder::new(BufReader::new(file))
+                    .map_err(|e| ThumbnailError::image(path, e))?
+                    .into_frames()
+            }
+            AnimationInput::Bytes(bytes) => GifDecoder::new(Cursor::new(bytes))
+                .map_err(|e| ThumbnailError::image(input_path, e))?
+                .into_frames(),
+        };
+
+        // Thumbnails are small, so we build the whole GIF in memory, and
+        // any errors while encoding it are about the original GIF.
+        let to_save_error = |source| ThumbnailError::ImageSaveError {
+            path: input_path.to_path_buf(),
+            source,
+        };
+
+        let mut bytes = Vec::new();
+        let mut encoder = GifEncoder::new(&mut bytes);
+        encoder
+            .set_repeat(Repeat::Infinite)
+            .map_err(to_save_error)?;
+
+        for frame in frames {
+            let frame = frame.map_err(|e| ThumbnailError::image(input_path, e))?;
+            let delay = frame.delay();
+            let mut resized =
+                imageops::resize(frame.buffer(), job.width, job.height, FilterType::Lanczos3);
+
+            if let Some(background) = job.background {
+                composite(&mut resized, background);
+            }
+
+            encoder
+                .encode_frame(Frame::from_parts(resized, 0, 0, delay))
+                .map_err(to_save_error)?;
+        }
+        drop(encoder);
+
+        let bytes = match job.output {
+            AnimationOutput::Path(path) => {
+                fs::write(path, bytes).map_err(|e| ThumbnailError::io(path, e))?;
+                None
+            }
+            AnimationOutput::Memory => Some(bytes),
+        };
+
+        Ok(EncodedAnimation {
+            width: job.width,
+            height: job.height,
+            ffmpeg_args: None,
+            bytes,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test_animated_encoder {
+    use std::path::PathBuf;
+
+    use image::codecs::gif::GifDecoder;
+    use image::{AnimationDecoder, ImageDecoder};
+
+    use super::*;
+    use crate::decode_limits::DecodeLimits;
+    use crate::gif_animation_length::gif_animation_length;
+    use crate::test_utils::{ffmpeg_is_installed, get_video_length, test_dir};
+
+    #[test]
+    fn it_creates_a_resized_gif() {
+        let out_dir = test_dir();
+        std::fs::create_dir_all(&out_dir).unwrap();
+        let output = out_dir.join("animated_squares.gif");
+
+        let job = AnimationJob {
+            input: AnimationInput::Path(&PathBuf::from("src/tests/animated_squares.gif")),
+            output: AnimationOutput::Path(&output),
+            width: 15,
+            height: 15,
+            start: None,
+            duration: None,
+            background: None,
+        };
+
+        let encoded = GifThumbnailEncoder.encode(&job).unwrap();
+        assert_eq!((encoded.width, encoded.height), (15, 15));
+
+        let decoder = GifDecoder::new(BufReader::new(File::open(&output).unwrap())).unwrap();
+        assert_eq!(decoder.dimensions(), (15, 15));
+        assert!(decoder.into_frames().count() > 1);
+    }
+
+    #[test]
+    fn it_fills_in_transparent_pixels_with_the_background() {
+        let out_dir = test_dir();
+        std::fs::create_dir_all(&out_dir).unwrap();
+        let output = out_dir.join("transparent_squares.gif");
+
+        let job = AnimationJob {
+            input: AnimationInput::Path(&PathBuf::from("src/tests/transparent_squares.gif")),
+            output: AnimationOutput::Path(&output),
+            width: 16,
+            height: 16,
+            start: None,
+            duration: None,
+            background: Some("#ffffff".parse().unwrap()),
+        };
+
+        GifThumbnailEncoder.encode(&job).unwrap();
+
+        let decoder = GifDecoder::new(BufReader::new(File::open(&output).unwrap())).unwrap();
+        let first_frame = decoder.into_frames().next().unwrap().unwrap();
+        let buffer = first_frame.buffer();
+
+        for (x, y) in [(0, 0), (15, 0), (0, 15), (15, 15)] {
+            assert_eq!(buffer.get_pixel(x, y), &Rgba([255, 255, 255, 255]));
+        }
+        assert_eq!(buffer.get_pixel(8, 8), &Rgba([255, 0, 0, 255]));
+    }
+
+    #[test]
+    fn it_keeps_transparent_pixels_if_there_is_no_background() {
+        let out_dir = test_dir();
+        std::fs::create_dir_all(&out_dir).unwrap();
+        let output = out_dir.join("transparent_squares.gif");
+
+        let job = AnimationJob {
+            input: AnimationInput::Path(&PathBuf::from("src/tests/transparent_squares.gif")),
+            output: AnimationOutput::Path(&output),
+            width: 16,
+            height: 16,
+            start: None,
+            duration: None,
+            background: None,
+        };
+
+        GifThumbnailEncoder.encode(&job).unwrap();
+
+        let decoder = GifDecoder::new(BufReader::new(File::open(&output).unwrap())).unwrap();
+        let first_frame = decoder.into_frames().next().unwrap().unwrap();
+
+        assert_eq!(first_frame.buffer().get_pixel(0, 0).0[3], 0);
+    }
+
+    #[test]
+    fn it_builds_a_filter_graph_with_a_background() {
+        assert_eq!(
+            composite_filter("scale=14:14", Colour::BLACK),
+            "[0:v]scale=14:14,format=yuva444p,split[a][b];\
+             [a]drawbox=c=0x000000:t=fill:replace=1[bg];[bg][b]overlay"
+        );
+    }
+
+    #[test]
+    fn the_video_has_the_same_frames_and_timing_as_the_gif() {
+        if !ffmpeg_is_installed() {
+            return;
+        }
+
+        let gif_path = PathBuf::from("src/tests/transparent_squares.gif");
+        let out_dir = test_dir();
+        std::fs::create_dir_all(&out_dir).unwrap();
+        let output = out_dir.join("transparent_squares.mp4");
+
+        let job = AnimationJob {
+            input: AnimationInput::Path(&gif_path),
+            output: AnimationOutput::Path(&output),
+            width: 16,
+            height: 16,
+            start: None,
+            duration: None,
+            background: Some(Colour::BLACK),
+        };
+
+        FfmpegEncoder::default().encode(&job).unwrap();
+
+        let (frames, duration) = gif_animation_length(
+            File::open(&gif_path).unwrap(),
+            &gif_path,
+            &DecodeLimits::default(),
+        )
+        .unwrap();
+        let (video_frames, video_duration) = get_video_length(&output);
+
+        assert_eq!(video_frames, frames);
+        assert!(
+            (video_duration - duration.as_secs_f64()).abs() < 0.05,
+            "the video lasts {}s, but the GIF lasts {:?}",
+            video_duration,
+            duration
+        );
+    }
+
+    #[test]
+    fn the_gif_encoder_cannot_create_video_previews() {
+        let job = AnimationJob {
+            input: AnimationInput::Path(&PathBuf::from("cat.mp4")),
+            output: AnimationOutput::Path(&PathBuf::from("cat.gif")),
+            width: 16,
+            height: 16,
+            start: Some(1.0),
+            duration: Some(3.0),
+            background: None,
+        };
+
+        assert!(GifThumbnailEncoder.encode(&job).is_err());
+    }
+
+    #[test]
+    fn it_creates_a_gif_in_memory() {
+        let input = std::fs::read("src/tests/animated_squares.gif").unwrap();
+
+        let job = AnimationJob {
+            input: AnimationInput::Bytes(&input),
+            output: AnimationOutput::Memory,
+            width: 15,
+            height: 15,
+            start: None,
+            duration: None,
+            background: None,
+        };
+
+        let encoded = GifThumbnailEncoder.encode(&job).unwrap();
+
+        let decoder = GifDecoder::new(Cursor::new(encoded.bytes.unwrap())).unwrap();
+        assert_eq!(decoder.dimensions(), (15, 15));
+        assert!(decoder.into_frames().count() > 1);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn it_streams_through_ffmpeg_pipes() {
+        use std::os::unix::fs::PermissionsExt;
+
+        // This fake ffmpeg ignores its arguments, and copies stdin
+        // to stdout.
+        let dir = test_dir();
+        std::fs::create_dir_all(&dir).unwrap();
+        let fake_ffmpeg = dir.join("ffmpeg");
+        std::fs::write(&fake_ffmpeg, "#!/bin/sh\ncat\n").unwrap();
+        std::fs::set_permissions(&fake_ffmpeg, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let encoder = FfmpegEncoder {
+            ffmpeg: FfmpegConfig {
+                ffmpeg: fake_ffmpeg,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        let job = AnimationJob {
+            input: AnimationInput::Bytes(b"GIF89a"),
+            output: AnimationOutput::Memory,
+            width: 16,
+            height: 16,
+            start: None,
+            duration: None,
+            background: None,
+        };
+
+        let encoded = encoder.encode(&job).unwrap();
+        let args = encoded.ffmpeg_args.unwrap();
+
+        assert_eq!(encoded.bytes.unwrap(), b"GIF89a");
+        assert_eq!(args[0..2], ["-i", "pipe:0"]);
+        assert!(args.ends_with(&[
+            "-f".to_string(),
+            "mp4".to_string(),
+            "-movflags".to_string(),
+            "frag_keyframe+empty_moov".to_string(),
+            "pipe:1".to_string()
+        ]));
+    }
+}
@@ -0,0 +1,173 @@
+use std::collections::HashSet;
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+/// A file which is written to a temporary path, and only appears at its
+/// final path once it's complete.
+///
+/// The temporary file is in the same directory as the final file, so
+/// we can move it into place with an atomic rename -- anybody looking at
+/// the final path sees either the old file or the complete new one,
+/// never a half-written thumbnail.
+///
+/// If an `AtomicFile` is dropped without being persisted, e.g. because
+/// something went wrong while writing it, the temporary file is deleted.
+#[derive(Debug)]
+pub struct AtomicFile {
+    temp_path: PathBuf,
+    path: PathBuf,
+}
+
+/// Every temporary file (or lock file, see `OutputLock`) which hasn't
+/// been persisted or cleaned up yet, so we can delete them if the process
+/// is interrupted.
+static TEMP_FILES: Mutex<Option<HashSet<PathBuf>>> = Mutex::new(None);
+
+/// A counter to make the temporary paths unique within this process.
+static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+impl AtomicFile {
+    /// Prepare to write a file to `path`.
+    pub fn new(path: &Path) -> AtomicFile {
+        let temp_path = temp_path_for(path);
+        register_temp_file(&temp_path);
+
+        AtomicFile {
+            temp_path,
+            path: path.to_path_buf(),
+        }
+    }
+
+    /// The path to write the file to.
+    pub fn temp_path(&self) -> &Path {
+        &self.temp_path
+    }
+
+    /// Move the complete file to its final path.
+    pub fn persist(self) -> io::Result<()> {
+        fs::rename(&self.temp_path, &self.path)
+    }
+}
+
+impl Drop for AtomicFile {
+    fn drop(&mut self) {
+        // If the file was persisted this fails, because the temporary
+        // file is already gone -- which is fine.
+        let _ = fs::remove_file(&self.temp_path);
+        unregister_temp_file(&self.temp_path);
+    }
+}
+
+/// Remember a file that `remove_temp_files` should delete, unless we
+/// clean it up ourselves first.
+pub(crate) fn register_temp_file(path: &Path) {
+    TEMP_FILES
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .get_or_insert_with(HashSet::new)
+        .insert(path.to_path_buf());
+}
+
+/// Forget a file, because it's been persisted or deleted.
+pub(crate) fn unregister_temp_file(path: &Path) {
+    if let Some(temp_files) = TEMP_FILES
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .as_mut()
+    {
+        temp_files.remove(path);
+    }
+}
+
+/// Delete every temporary file which hasn't been persisted yet, and
+/// every lock file we're holding.
+///
+/// Destructors don't run if the process is killed by a signal, so
+/// call this from a signal handler to avoid leaving partial thumbnails
+/// and stale lock files lying around.
+pub fn remove_temp_files() {
+    let mut temp_files = TEMP_FILES.lock().unwrap_or_else(|e| e.into_inner());
+
+    for path in temp_files.take().unwrap_or_default() {
+        let _ = fs::remove_file(path);
+    }
+}
+
+/// Returns a temporary path next to `path`, e.g. `.cat.1234-0.tmp.jpg`
+/// for `cat.jpg`.
+///
+/// We keep the extension, because ffmpeg uses it to choose the format
+/// of the file, and we start with a dot so it's hidden from directory
+/// listings on Unix.
+fn temp_path_for(path: &Path) -> PathBuf {
+    let unique = format!(
+        ".{}-{}.tmp",
+        std::process::id(),
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    );
+
+    let mut name = OsString::from(".");
+    name.push(path.file_stem().unwrap_or_default());
+    name.push(unique);
+    if let Some(ext) = path.extension() {
+        name.push(".");
+        name.push(ext);
+    }
+
+    path.with_file_name(name)
+}
+
+#[cfg(test)]
+mod test_atomic_file {
+    use super::*;
+    use crate::test_utils::test_dir;
+
+    #[test]
+    fn it_only_creates_the_file_when_persisted() {
+        let dir = test_dir();
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("cat.jpg");
+
+        let file = AtomicFile::new(&path);
+        fs::write(file.temp_path(), "meow").unwrap();
+
+        assert!(!path.exists());
+        assert_eq!(file.temp_path().parent(), Some(dir.as_path()));
+        assert_eq!(file.temp_path().extension().unwrap(), "jpg");
+
+        let temp_path = file.temp_path().to_path_buf();
+        file.persist().unwrap();
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "meow");
+        assert!(!temp_path.exists());
+    }
+
+    #[test]
+    fn it_cleans_up_if_not_persisted() {
+        let dir = test_dir();
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("cat.jpg");
+
+        let file = AtomicFile::new(&path);
+        let temp_path = file.temp_path().to_path_buf();
+        fs::write(&temp_path, "half a cat").unwrap();
+        drop(file);
+
+        assert!(!temp_path.exists());
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn it_uses_a_different_temp_path_each_time() {
+        let path = PathBuf::from("cat.jpg");
+
+        assert_ne!(
+            AtomicFile::new(&path).temp_path(),
+            AtomicFile::new(&path).temp_path()
+        );
+    }
+}
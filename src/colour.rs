@@ -0,0 +1,116 @@
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{Serialize, Serializer};
+
+/// An opaque RGB colour, e.g. the background behind a thumbnail.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Colour {
+    pub red: u8,
+    pub green: u8,
+    pub blue: u8,
+}
+
+impl Colour {
+    pub const BLACK: Colour = Colour {
+        red: 0,
+        green: 0,
+        blue: 0,
+    };
+
+    /// Returns the colour in the `0xRRGGBB` form used by ffmpeg filters.
+    pub fn to_ffmpeg(self) -> String {
+        format!("0x{:02x}{:02x}{:02x}", self.red, self.green, self.blue)
+    }
+}
+
+/// Parse a colour from a hex string like `#ff0000` or `#f00` (the `#`
+/// is optional), or one of the names `black` and `white`.
+impl FromStr for Colour {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let hex = match s.to_lowercase().as_str() {
+            "black" => "000000".to_string(),
+            "white" => "ffffff".to_string(),
+            other => {
+                let digits = other.strip_prefix('#').unwrap_or(other);
+
+                match digits.len() {
+                    3 => digits.chars().flat_map(|c| [c, c]).collect(),
+                    _ => digits.to_string(),
+                }
+            }
+        };
+
+        if hex.len() != 6 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(format!(
+                "{:?} is not a colour; use a hex string like #ffffff",
+                s
+            ));
+        }
+
+        let component = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).unwrap();
+
+        Ok(Colour {
+            red: component(0),
+            green: component(2),
+            blue: component(4),
+        })
+    }
+}
+
+impl fmt::Display for Colour {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{:02x}{:02x}{:02x}", self.red, self.green, self.blue)
+    }
+}
+
+impl Serialize for Colour {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+#[cfg(test)]
+mod test_colour {
+    use super::*;
+
+    macro_rules! parse_colour_tests {
+        ($($name:ident: $value:expr,)*) => {
+        $(
+            #[test]
+            fn $name() {
+                let (input, expected) = $value;
+
+                assert_eq!(input.parse::<Colour>(), expected);
+            }
+        )*
+        }
+    }
+
+    parse_colour_tests! {
+        long_hex:           ("#ff8000", Ok(Colour { red: 255, green: 128, blue: 0 })),
+        long_hex_no_hash:   ("ff8000",  Ok(Colour { red: 255, green: 128, blue: 0 })),
+        short_hex:          ("#f80",    Ok(Colour { red: 255, green: 136, blue: 0 })),
+        uppercase_hex:      ("#FF8000", Ok(Colour { red: 255, green: 128, blue: 0 })),
+        named_black:        ("black",   Ok(Colour::BLACK)),
+        named_white:        ("White",   Ok(Colour { red: 255, green: 255, blue: 255 })),
+
+        not_hex:            ("#gggggg", Err("\"#gggggg\" is not a colour; use a hex string like #ffffff".to_string())),
+        wrong_length:       ("#ff80",   Err("\"#ff80\" is not a colour; use a hex string like #ffffff".to_string())),
+        empty:              ("",        Err("\"\" is not a colour; use a hex string like #ffffff".to_string())),
+    }
+
+    #[test]
+    fn it_formats_colours_for_ffmpeg() {
+        let colour = Colour {
+            red: 255,
+            green: 128,
+            blue: 0,
+        };
+
+        assert_eq!(colour.to_ffmpeg(), "0xff8000");
+        assert_eq!(colour.to_string(), "#ff8000");
+    }
+}
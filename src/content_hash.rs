@@ -0,0 +1,37 @@
+use std::io::{self, Read};
+
+use sha2::{Digest, Sha256};
+
+/// Returns the SHA-256 hash of everything in `reader`, as a hex string.
+///
+/// We use this to name thumbnails after the contents of the original
+/// file, so identical files get identical names.
+pub fn content_hash(mut reader: impl Read) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut reader, &mut hasher)?;
+
+    Ok(hex(&hasher.finalize()))
+}
+
+/// Format some bytes as a lowercase hex string.
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod test_content_hash {
+    use super::*;
+
+    #[test]
+    fn it_hashes_the_contents() {
+        assert_eq!(
+            content_hash("hello world".as_bytes()).unwrap(),
+            "b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9"
+        );
+    }
+
+    #[test]
+    fn it_formats_bytes_as_hex() {
+        assert_eq!(hex(&[0, 15, 16, 255]), "000f10ff");
+    }
+}
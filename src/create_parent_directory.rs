@@ -1,15 +1,18 @@
 use std::fs;
-use std::io;
-use std::path::PathBuf;
+use std::path::Path;
+
+use crate::errors::ThumbnailError;
 
 /// Create the parent directory of a given path.
 ///
 /// Example:
 ///
-///     create_parent_directory("path/to/images/index.html")
-///      ~> creates "path/to/images/"
+/// ```text
+/// create_parent_directory("path/to/images/index.html")
+///  ~> creates "path/to/images/"
+/// ```
 ///
-pub fn create_parent_directory(path: &PathBuf) -> io::Result<()> {
+pub fn create_parent_directory(path: &Path) -> Result<(), ThumbnailError> {
     // Quoting from the Rust docs for PathBuf.parent() [1]:
     //
     //     Returns None if the path terminates in a root or prefix,
@@ -21,7 +24,7 @@ pub fn create_parent_directory(path: &PathBuf) -> io::Result<()> {
     // [1]: https://doc.rust-lang.org/std/path/struct.PathBuf.html#method.parent
     let parent_dir = path.parent().unwrap();
 
-    fs::create_dir_all(&parent_dir)
+    fs::create_dir_all(parent_dir).map_err(|e| ThumbnailError::io(parent_dir, e))
 }
 
 #[cfg(test)]
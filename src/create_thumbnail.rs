@@ -1,47 +1,938 @@
-use std::path::PathBuf;
-use std::process::Command;
-use std::str;
+use std::ffi::OsStr;
+use std::fs::File;
+use std::io::{self, Cursor, Read};
+use std::path::{Path, PathBuf};
 
 use image::imageops::FilterType;
-use image::{DynamicImage, ImageDecoder, ImageReader};
+use image::{DynamicImage, GenericImageView, ImageError, ImageFormat};
+use serde::{Deserialize, Serialize};
 
+use crate::animated_encoder::{AnimatedEncoder, AnimationInput, AnimationJob, AnimationOutput};
+use crate::atomic_file::AtomicFile;
+use crate::colour::Colour;
+use crate::content_hash::content_hash;
 use crate::create_parent_directory::create_parent_directory;
+use crate::create_video_thumbnail::{create_video_thumbnail, is_video, probe_video, VideoInfo};
 use crate::errors::ThumbnailError;
-use crate::get_thumbnail_dimensions::{get_thumbnail_dimensions, TargetDimension};
-use crate::is_animated_gif::is_animated_gif;
+use crate::get_thumbnail_dimensions::{
+    calculate_dimensions, get_thumbnail_dimensions, TargetDimension,
+};
+use crate::if_exists::resolve_output_path;
+use crate::is_same_path::is_same_path;
+use crate::name_template::NameValues;
+use crate::options::ThumbnailOptions;
+use crate::output_lock::OutputLock;
+use crate::source_image::SourceImage;
+use crate::validate_out_dir::validate_out_dir;
 
-/// Create a thumbnail for the image, and return the relative path of
-/// the thumbnail within the collection folder.
+/// Information about a newly-created thumbnail.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Thumbnail {
+    /// Path to the thumbnail
+    #[serde(with = "crate::serde_path")]
+    pub path: PathBuf,
+
+    /// Width of the thumbnail, in pixels
+    pub width: u32,
+
+    /// Height of the thumbnail, in pixels
+    pub height: u32,
+
+    /// Path to the preview clip, if we created one
+    #[serde(
+        default,
+        with = "crate::serde_path::option",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub preview_path: Option<PathBuf>,
+
+    /// The arguments we passed to ffmpeg, if we used it to create
+    /// the thumbnail or preview clip
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ffmpeg_args: Option<Vec<String>>,
+
+    /// Anything odd we noticed while creating the thumbnail, e.g. an
+    /// image whose extension doesn't match its contents
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<String>,
+
+    /// Whether there was already a thumbnail at `path`, which we left
+    /// alone because `options.if_exists` is `IfExists::Skip`
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub skipped: bool,
+}
+
+fn is_false(b: &bool) -> bool {
+    !b
+}
+
+/// A thumbnail which has been encoded in memory, rather than saved
+/// to a file.
+#[derive(Debug)]
+pub struct EncodedThumbnail {
+    /// The encoded thumbnail, e.g. the contents of a PNG or MP4 file
+    pub bytes: Vec<u8>,
+
+    /// Width of the thumbnail, in pixels
+    pub width: u32,
+
+    /// Height of the thumbnail, in pixels
+    pub height: u32,
+
+    /// The usual file extension for the format of the thumbnail,
+    /// e.g. `png` or `mp4`
+    pub extension: String,
+
+    /// Whether the thumbnail is animated, i.e. a video or animated GIF
+    pub is_animated: bool,
+
+    /// The arguments we passed to ffmpeg, if we used it to create
+    /// the thumbnail
+    pub ffmpeg_args: Option<Vec<String>>,
+
+    /// Anything odd we noticed while creating the thumbnail
+    pub warnings: Vec<String>,
+}
+
+/// Create a thumbnail for the image in `out_dir`, and return a
+/// [`Thumbnail`] describing it.  Its `path` includes `out_dir`.
+///
+/// Before we read the image, we check `out_dir` is somewhere we can save
+/// the thumbnail (see [`validate_out_dir`]), so a bad output directory
+/// fails quickly with an error which names it.
+///
+/// It's safe to call this from several processes at once with the same
+/// `out_dir`: while we're creating a thumbnail, we hold an advisory lock
+/// on its path, and anybody else who wants to create a thumbnail at the
+/// same path waits for us to finish.  Then `options.if_exists` (or
+/// content-addressed storage) decides whether they reuse our thumbnail
+/// or replace it.
 pub fn create_thumbnail(
-    path: &PathBuf,
-    out_dir: &PathBuf,
+    path: &Path,
+    out_dir: &Path,
+    target: TargetDimension,
+    options: &ThumbnailOptions,
+) -> Result<Thumbnail, ThumbnailError> {
+    let file_name = PathBuf::from(
+        path.file_name()
+            .ok_or_else(|| ThumbnailError::MissingFileName(path.to_path_buf()))?,
+    );
+
+    // Check we can write to the output directory before we do any work.
+    // We check the final path again once we know the thumbnail's name,
+    // but without a template, the name is usually the same as the
+    // original file, so we can catch that early.
+    validate_out_dir(out_dir)?;
+    create_parent_directory(&out_dir.join(&file_name))?;
+    let uses_default_name = options.name_template.is_none() && !options.content_addressed;
+    if uses_default_name && is_same_path(path, &out_dir.join(&file_name)) {
+        return Err(ThumbnailError::SameInputOutputPath(
+            out_dir.join(&file_name),
+        ));
+    }
+
+    if is_video(path) {
+        let info = probe_video(path, &options.ffmpeg)?;
+        let (thumbnail_path, dimensions) =
+            video_thumbnail_path(path, out_dir, &info, target, options)?;
+        prepare_output_path(path, &thumbnail_path)?;
+        let _lock = OutputLock::acquire(&thumbnail_path)
+            .map_err(|e| ThumbnailError::io(&thumbnail_path, e))?;
+
+        let _preview_lock = lock_preview_clip(&thumbnail_path, options)?;
+
+        let preview_extension = options
+            .video_preview_seconds
+            .map(|_| options.animated_encoder.extension());
+        if let Some(thumbnail) =
+            existing_thumbnail(&thumbnail_path, dimensions, preview_extension, options)
+        {
+            return Ok(thumbnail);
+        }
+
+        return create_video_thumbnail(
+            path,
+            &info,
+            &thumbnail_path,
+            ImageFormat::Jpeg,
+            target,
+            options,
+        );
+    }
+
+    // We read the file once, and work out everything we need from the
+    // headers; the pixel data is only decoded when we resize it.
+    let source = SourceImage::open(path, &options.limits)?;
+    let (thumbnail_path, dimensions) =
+        image_thumbnail_path(path, out_dir, &source, target, options)?;
+    prepare_output_path(path, &thumbnail_path)?;
+    let _lock =
+        OutputLock::acquire(&thumbnail_path).map_err(|e| ThumbnailError::io(&thumbnail_path, e))?;
+
+    if let Some(mut thumbnail) = existing_thumbnail(&thumbnail_path, dimensions, None, options) {
+        thumbnail
+            .warnings
+            .extend(extension_warning(path, source.format));
+        return Ok(thumbnail);
+    }
+
+    save_thumbnail(
+        path,
+        &source,
+        &thumbnail_path,
+        source.format,
+        target,
+        options,
+    )
+}
+
+/// Returns where we'll save the still thumbnail of a video in `out_dir`,
+/// and its dimensions.
+pub(crate) fn video_thumbnail_path(
+    path: &Path,
+    out_dir: &Path,
+    info: &VideoInfo,
+    target: TargetDimension,
+    options: &ThumbnailOptions,
+) -> Result<(PathBuf, (u32, u32)), ThumbnailError> {
+    let file_name = PathBuf::from(
+        path.file_name()
+            .ok_or_else(|| ThumbnailError::MissingFileName(path.to_path_buf()))?,
+    );
+    let dimensions = calculate_dimensions((info.width, info.height), target);
+
+    let thumbnail_path = out_dir.join(thumbnail_name(
+        path,
+        file_name.with_extension("jpg"),
+        "jpg",
+        dimensions,
+        target,
+        File::open(path).map_err(|e| ThumbnailError::io(path, e))?,
+        options,
+    )?);
+
+    Ok((thumbnail_path, dimensions))
+}
+
+/// Returns where we'll save the thumbnail of an image in `out_dir`, and
+/// its dimensions.
+///
+/// For animated images, these are the dimensions the encoder will
+/// actually create, which may differ slightly from the ones we ask for.
+pub(crate) fn image_thumbnail_path(
+    path: &Path,
+    out_dir: &Path,
+    source: &SourceImage,
+    target: TargetDimension,
+    options: &ThumbnailOptions,
+) -> Result<(PathBuf, (u32, u32)), ThumbnailError> {
+    let file_name = PathBuf::from(
+        path.file_name()
+            .ok_or_else(|| ThumbnailError::MissingFileName(path.to_path_buf()))?,
+    );
+    let (width, height) = get_thumbnail_dimensions(source, target);
+
+    let encoder = &options.animated_encoder;
+    let (default_name, extension, dimensions) = if source.is_animated {
+        (
+            file_name.with_extension(encoder.extension()),
+            encoder.extension(),
+            encoder.output_dimensions(width, height),
+        )
+    } else {
+        (
+            static_thumbnail_name(path, source.format)?,
+            source.format.extensions_str()[0],
+            (width, height),
+        )
+    };
+
+    let thumbnail_path = out_dir.join(thumbnail_name(
+        path,
+        default_name,
+        extension,
+        dimensions,
+        target,
+        source.bytes(),
+        options,
+    )?);
+
+    Ok((thumbnail_path, dimensions))
+}
+
+/// Returns the name of a thumbnail, relative to the output directory.
+///
+/// If `options` asks for content-addressed storage, we name it after
+/// a hash of `contents` and the options; if there's a name template,
+/// we fill it in; otherwise we use `default_name`.  We only read
+/// `contents` if we need to hash it.
+fn thumbnail_name(
+    path: &Path,
+    default_name: PathBuf,
+    extension: &str,
+    dimensions: (u32, u32),
     target: TargetDimension,
+    contents: impl Read,
+    options: &ThumbnailOptions,
 ) -> Result<PathBuf, ThumbnailError> {
-    let file_name = path.file_name().ok_or(ThumbnailError::MissingFileName)?;
-    let thumbnail_path = out_dir.join(file_name);
-    create_parent_directory(&thumbnail_path)?;
+    if options.content_addressed {
+        return content_addressed_name(contents, extension, dimensions, target, options)
+            .map_err(|e| ThumbnailError::io(path, e));
+    }
+
+    let template = match &options.name_template {
+        Some(template) => template,
+        None => return Ok(default_name),
+    };
+
+    let hash = if template.uses_hash() {
+        Some(content_hash(contents).map_err(|e| ThumbnailError::io(path, e))?)
+    } else {
+        None
+    };
+
+    Ok(template.render(&NameValues {
+        path,
+        extension,
+        dimensions,
+        target,
+        hash,
+    }))
+}
+
+/// Returns the content-addressed name of a thumbnail, e.g. `ab/cdef….webp`.
+///
+/// The name is a hash of the original file and every option which
+/// affects the thumbnail, so two identical files get the same name,
+/// but changing the size or encoder settings gets a new one.  The first
+/// two characters of the hash are a subdirectory, so we don't put too
+/// many files in one directory.
+fn content_addressed_name(
+    contents: impl Read,
+    extension: &str,
+    dimensions: (u32, u32),
+    target: TargetDimension,
+    options: &ThumbnailOptions,
+) -> io::Result<PathBuf> {
+    let key = ContentKey {
+        version: "v1",
+        target: target.to_string(),
+        dimensions,
+        extension,
+        background: options.background,
+        use_embedded_preview: options.use_embedded_preview,
+        encoder: options.animated_encoder.settings(),
+        video_frame_at: options.video_frame_at,
+        video_preview_seconds: options.video_preview_seconds,
+    };
+    let key = serde_json::to_vec(&key)?;
+
+    let hash = content_hash(contents.chain(key.as_slice()))?;
+
+    Ok(PathBuf::from(&hash[..2]).join(format!("{}.{}", &hash[2..], extension)))
+}
+
+/// Everything apart from the original file which goes into the name of
+/// a content-addressed thumbnail.
+///
+/// We hash this as JSON, so the names stay the same between releases.
+/// If you change what goes in here, change the `version` too, so we
+/// don't reuse a thumbnail which was created with different options.
+#[derive(Serialize)]
+struct ContentKey<'a> {
+    version: &'static str,
+    target: String,
+    dimensions: (u32, u32),
+    extension: &'a str,
+    background: Option<Colour>,
+    use_embedded_preview: bool,
+    encoder: serde_json::Value,
+    video_frame_at: Option<f64>,
+    video_preview_seconds: Option<f64>,
+}
+
+/// Returns the thumbnail at `thumbnail_path` if we've already created it.
+///
+/// With content-addressed storage, a thumbnail with the same name was
+/// created from an identical file with identical options, so we can
+/// reuse it rather than creating it again.
+pub(crate) fn existing_thumbnail(
+    thumbnail_path: &Path,
+    (width, height): (u32, u32),
+    preview_extension: Option<&str>,
+    options: &ThumbnailOptions,
+) -> Option<Thumbnail> {
+    if !options.content_addressed || !thumbnail_path.exists() {
+        return None;
+    }
+
+    let preview_path = match preview_extension {
+        Some(extension) => {
+            let preview_path = thumbnail_path.with_extension(extension);
+            if !preview_path.exists() {
+                return None;
+            }
+            Some(preview_path)
+        }
+        None => None,
+    };
+
+    Some(Thumbnail {
+        path: thumbnail_path.to_path_buf(),
+        width,
+        height,
+        preview_path,
+        ffmpeg_args: None,
+        warnings: vec![],
+        skipped: false,
+    })
+}
+
+/// Get ready to write a thumbnail to `thumbnail_path`: create its parent
+/// directory, and make sure we won't overwrite the original image.
+fn prepare_output_path(path: &Path, thumbnail_path: &Path) -> Result<(), ThumbnailError> {
+    create_parent_directory(thumbnail_path)?;
+
+    if is_same_path(path, thumbnail_path) {
+        return Err(ThumbnailError::SameInputOutputPath(
+            thumbnail_path.to_path_buf(),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Create a thumbnail for the image, and save it at exactly `thumbnail_path`.
+///
+/// If `path` is `-`, we read the image from stdin.  Static thumbnails are
+/// saved as `format` if it's set, or else the format is inferred from
+/// the extension of `thumbnail_path`.  Animated thumbnails are always
+/// created by the animated encoder, so `thumbnail_path` has to have the
+/// right extension for the encoder.
+///
+/// Like [`create_thumbnail`], we hold a lock on `thumbnail_path` while we
+/// create the thumbnail, so concurrent calls with the same path don't race.
+pub fn create_thumbnail_at(
+    path: &Path,
+    thumbnail_path: &Path,
+    target: TargetDimension,
+    format: Option<ImageFormat>,
+    options: &ThumbnailOptions,
+) -> Result<Thumbnail, ThumbnailError> {
+    let is_stdin = path == Path::new("-");
+
+    validate_out_dir(thumbnail_path.parent().unwrap_or(Path::new("")))?;
+    if is_stdin {
+        create_parent_directory(thumbnail_path)?;
+    } else {
+        prepare_output_path(path, thumbnail_path)?;
+    }
+    let _lock =
+        OutputLock::acquire(thumbnail_path).map_err(|e| ThumbnailError::io(thumbnail_path, e))?;
+
+    if !is_stdin && is_video(path) {
+        let _preview_lock = lock_preview_clip(thumbnail_path, options)?;
+        let info = probe_video(path, &options.ffmpeg)?;
+        return create_video_thumbnail(
+            path,
+            &info,
+            thumbnail_path,
+            static_format(thumbnail_path, format)?,
+            target,
+            options,
+        );
+    }
+
+    let source = if is_stdin {
+        SourceImage::from_reader(io::stdin().lock(), &options.limits)?
+    } else {
+        SourceImage::open(path, &options.limits)?
+    };
+
+    let format = output_format(&source, thumbnail_path, format, options)?;
+
+    save_thumbnail(path, &source, thumbnail_path, format, target, options)
+}
+
+/// Lock the path of the preview clip we create alongside the still
+/// thumbnail of a video at `thumbnail_path`, if we're creating one.
+///
+/// The clip is named after the still, so whoever holds the lock on the
+/// still also needs this one.  We always take it second, so two processes
+/// can't deadlock.
+fn lock_preview_clip(
+    thumbnail_path: &Path,
+    options: &ThumbnailOptions,
+) -> Result<Option<OutputLock>, ThumbnailError> {
+    if options.video_preview_seconds.is_none() {
+        return Ok(None);
+    }
+
+    let preview_path = thumbnail_path.with_extension(options.animated_encoder.extension());
+    OutputLock::acquire(&preview_path)
+        .map(Some)
+        .map_err(|e| ThumbnailError::io(&preview_path, e))
+}
+
+/// Returns the format to save a static thumbnail as: `format` if it's
+/// set, or else the format which matches the extension of `thumbnail_path`.
+pub(crate) fn static_format(
+    thumbnail_path: &Path,
+    format: Option<ImageFormat>,
+) -> Result<ImageFormat, ThumbnailError> {
+    match format.or_else(|| ImageFormat::from_path(thumbnail_path).ok()) {
+        Some(format) => Ok(format),
+        None => Err(ThumbnailError::InvalidOptions(format!(
+            "unable to tell what format to use for {}; use --format to choose one",
+            thumbnail_path.display()
+        ))),
+    }
+}
+
+/// Returns the format to save the thumbnail of `source` as, at exactly
+/// `thumbnail_path`.
+///
+/// Animated thumbnails are always created by the animated encoder, so
+/// `thumbnail_path` has to have the right extension for the encoder.
+pub(crate) fn output_format(
+    source: &SourceImage,
+    thumbnail_path: &Path,
+    format: Option<ImageFormat>,
+    options: &ThumbnailOptions,
+) -> Result<ImageFormat, ThumbnailError> {
+    if !source.is_animated {
+        return static_format(thumbnail_path, format);
+    }
+
+    let extension = options.animated_encoder.extension();
+    if thumbnail_path.extension() != Some(OsStr::new(extension)) {
+        return Err(ThumbnailError::InvalidOptions(format!(
+            "animated thumbnails are saved as .{}, so the output path must end in .{}",
+            extension, extension
+        )));
+    }
+
+    Ok(source.format)
+}
+
+/// Create a thumbnail for an image we've already read, and save it
+/// at `thumbnail_path`.  Static thumbnails are saved as `format`.
+fn save_thumbnail(
+    path: &Path,
+    source: &SourceImage,
+    thumbnail_path: &Path,
+    format: ImageFormat,
+    target: TargetDimension,
+    options: &ThumbnailOptions,
+) -> Result<Thumbnail, ThumbnailError> {
+    let (new_width, new_height) = get_thumbnail_dimensions(source, target);
+
+    let thumbnail_path = match resolve_output_path(thumbnail_path, options.if_exists)? {
+        Some(thumbnail_path) => thumbnail_path,
+        None => {
+            let (width, height) = if source.is_animated {
+                options
+                    .animated_encoder
+                    .output_dimensions(new_width, new_height)
+            } else {
+                (new_width, new_height)
+            };
+
+            return Ok(Thumbnail {
+                path: thumbnail_path.to_path_buf(),
+                width,
+                height,
+                preview_path: None,
+                ffmpeg_args: None,
+                warnings: extension_warning(path, source.format).into_iter().collect(),
+                skipped: true,
+            });
+        }
+    };
+    let thumbnail_path = thumbnail_path.as_path();
+
+    let mut thumbnail = if source.is_animated {
+        // If we read the GIF from a file, the encoder can read it again;
+        // if it came from stdin, it only exists in memory.
+        let input = if path == Path::new("-") {
+            AnimationInput::Bytes(source.bytes())
+        } else {
+            AnimationInput::Path(path)
+        };
+
+        create_animated_gif_thumbnail(
+            input,
+            thumbnail_path,
+            new_width,
+            new_height,
+            options.animated_encoder.as_ref(),
+            options.background,
+        )
+    } else {
+        create_static_thumbnail(
+            source,
+            thumbnail_path,
+            format,
+            new_width,
+            new_height,
+            options.use_embedded_preview,
+        )
+    }?;
+
+    thumbnail
+        .warnings
+        .extend(extension_warning(path, source.format));
+
+    Ok(thumbnail)
+}
+
+/// Create a thumbnail for the image, and return the encoded thumbnail
+/// rather than saving it to a file.
+///
+/// If `path` is `-`, we read the image from stdin.  See
+/// [`create_thumbnail_from_bytes`] for how we choose the format.
+///
+/// We can only read videos from a file, and only write their
+/// thumbnails to a directory, so this doesn't support videos.
+pub fn create_thumbnail_in_memory(
+    path: &Path,
+    target: TargetDimension,
+    format: Option<ImageFormat>,
+    options: &ThumbnailOptions,
+) -> Result<EncodedThumbnail, ThumbnailError> {
+    if is_video(path) {
+        return Err(ThumbnailError::InvalidOptions(
+            "video thumbnails can only be saved with --out-dir".to_string(),
+        ));
+    }
+
+    if path == Path::new("-") {
+        return create_thumbnail_from_reader(io::stdin().lock(), target, format, options);
+    }
+
+    let source = SourceImage::open(path, &options.limits)?;
+    let mut thumbnail = encode_thumbnail(&source, target, format, options)?;
+
+    thumbnail
+        .warnings
+        .extend(extension_warning(path, source.format));
+
+    Ok(thumbnail)
+}
+
+/// Create a thumbnail for an image which is already in memory, and
+/// return the encoded thumbnail.
+///
+/// Static thumbnails are encoded as `format` if it's set, or in the same
+/// format as the original image if we can write that format.  Animated
+/// GIFs are always encoded with the animated encoder in `options`.
+///
+/// The format of the image is detected from its contents.  Videos
+/// aren't supported.
+pub fn create_thumbnail_from_bytes(
+    bytes: &[u8],
+    target: TargetDimension,
+    format: Option<ImageFormat>,
+    options: &ThumbnailOptions,
+) -> Result<EncodedThumbnail, ThumbnailError> {
+    create_thumbnail_from_reader(bytes, target, format, options)
+}
+
+/// Create a thumbnail for an image read from `reader`, e.g. an object
+/// fetched from storage, and return the encoded thumbnail.
+///
+/// We read the whole image into memory, up to the `max_input_bytes` limit.
+/// This works the same way as [`create_thumbnail_from_bytes`].
+pub fn create_thumbnail_from_reader(
+    reader: impl Read,
+    target: TargetDimension,
+    format: Option<ImageFormat>,
+    options: &ThumbnailOptions,
+) -> Result<EncodedThumbnail, ThumbnailError> {
+    let source = SourceImage::from_reader(reader, &options.limits)?;
+
+    encode_thumbnail(&source, target, format, options)
+}
+
+/// Create a thumbnail for an image we've already read, and encode it
+/// in memory.
+fn encode_thumbnail(
+    source: &SourceImage,
+    target: TargetDimension,
+    format: Option<ImageFormat>,
+    options: &ThumbnailOptions,
+) -> Result<EncodedThumbnail, ThumbnailError> {
+    let (new_width, new_height) = get_thumbnail_dimensions(source, target);
+
+    if source.is_animated {
+        let encoded = options.animated_encoder.encode(&AnimationJob {
+            input: AnimationInput::Bytes(source.bytes()),
+            output: AnimationOutput::Memory,
+            width: new_width,
+            height: new_height,
+            start: None,
+            duration: None,
+            background: options.background,
+        })?;
+
+        return Ok(EncodedThumbnail {
+            bytes: encoded.bytes.unwrap_or_default(),
+            width: encoded.width,
+            height: encoded.height,
+            extension: options.animated_encoder.extension().to_string(),
+            is_animated: true,
+            ffmpeg_args: encoded.ffmpeg_args,
+            warnings: vec![],
+        });
+    }
+
+    let format = format.unwrap_or(source.format);
+    if !format.writing_enabled() {
+        return Err(ThumbnailError::InvalidOptions(format!(
+            "unable to write {} thumbnails; use --format to pick a different format",
+            format_name(format)
+        )));
+    }
+
+    let resized = resize_static(source, new_width, new_height, options.use_embedded_preview)?;
+    let bytes = encode_static_thumbnail(&resized, format, source.path())?;
+    let (width, height) = resized.dimensions();
+
+    Ok(EncodedThumbnail {
+        bytes,
+        width,
+        height,
+        extension: format.extensions_str()[0].to_string(),
+        is_animated: false,
+        ffmpeg_args: None,
+        warnings: vec![],
+    })
+}
+
+/// The name of an image format, as we show it in messages, e.g. `PNG`.
+pub fn format_name(format: ImageFormat) -> String {
+    format!("{:?}", format).to_uppercase()
+}
+
+/// Returns a warning if the extension of an image doesn't match the
+/// format we detected from its contents, e.g. a PNG named `photo.jpg`.
+///
+/// We don't warn about files with no extension.
+fn extension_warning(path: &Path, format: ImageFormat) -> Option<String> {
+    let extension = path.extension()?;
 
-    // Make sure we don't overwrite the original image with a thumbnail
-    if *path == thumbnail_path {
-        return Err(ThumbnailError::SameInputOutputPath);
+    match ImageFormat::from_extension(extension) {
+        Some(f) if f == format => None,
+        _ => Some(format!(
+            "{} looks like a {} image, but has the extension .{}",
+            path.display(),
+            format_name(format),
+            extension.to_string_lossy()
+        )),
     }
+}
 
-    let (new_width, new_height) = get_thumbnail_dimensions(&path, target)?;
+/// Returns the file name for a static thumbnail.
+///
+/// This is normally the same as the original image, but if the extension
+/// doesn't match the real format of the image, we replace it -- so the
+/// thumbnail is saved in the same format as the original, and has
+/// an extension which matches its contents.
+fn static_thumbnail_name(
+    image_path: &Path,
+    format: ImageFormat,
+) -> Result<PathBuf, ThumbnailError> {
+    let file_name = PathBuf::from(
+        image_path
+            .file_name()
+            .ok_or_else(|| ThumbnailError::MissingFileName(image_path.to_path_buf()))?,
+    );
 
-    if is_animated_gif(path)? {
-        create_animated_gif_thumbnail(path, out_dir, new_width, new_height)
+    if ImageFormat::from_path(&file_name).ok() == Some(format) {
+        Ok(file_name)
     } else {
-        create_static_thumbnail(path, out_dir, new_width, new_height)
+        Ok(file_name.with_extension(format.extensions_str()[0]))
     }
 }
 
+/// Create a thumbnail for an animated GIF.
+///
+/// This creates a looping thumbnail with the given encoder, which by
+/// default uses ffmpeg to create an MP4 file.  The dimensions of the
+/// thumbnail are the ones reported by the encoder, which may differ
+/// slightly from `width`×`height`.
+///
+/// The encoder writes to a temporary file, which is moved to
+/// `thumbnail_path` once it's complete.
+///
+/// The caller is responsible for checking that `thumbnail_path` isn't
+/// the original GIF.
+///
+pub fn create_animated_gif_thumbnail(
+    input: AnimationInput,
+    thumbnail_path: &Path,
+    width: u32,
+    height: u32,
+    encoder: &dyn AnimatedEncoder,
+    background: Option<Colour>,
+) -> Result<Thumbnail, ThumbnailError> {
+    let file = AtomicFile::new(thumbnail_path);
+
+    let encoded = encoder.encode(&AnimationJob {
+        input,
+        output: AnimationOutput::Path(file.temp_path()),
+        width,
+        height,
+        start: None,
+        duration: None,
+        background,
+    })?;
+
+    file.persist()
+        .map_err(|e| ThumbnailError::io(thumbnail_path, e))?;
+
+    Ok(Thumbnail {
+        path: thumbnail_path.to_path_buf(),
+        width: encoded.width,
+        height: encoded.height,
+        preview_path: None,
+        ffmpeg_args: encoded.ffmpeg_args,
+        warnings: vec![],
+        skipped: false,
+    })
+}
+
+/// Create a thumbnail for a static (non-animated) image, and save it
+/// as `format`.
+pub fn create_static_thumbnail(
+    source: &SourceImage,
+    thumbnail_path: &Path,
+    format: ImageFormat,
+    width: u32,
+    height: u32,
+    use_embedded_preview: bool,
+) -> Result<Thumbnail, ThumbnailError> {
+    let resized = resize_static(source, width, height, use_embedded_preview)?;
+    save_static_thumbnail(&resized, format, thumbnail_path)?;
+    let (width, height) = resized.dimensions();
+
+    Ok(Thumbnail {
+        path: thumbnail_path.to_path_buf(),
+        width,
+        height,
+        preview_path: None,
+        ffmpeg_args: None,
+        warnings: vec![],
+        skipped: false,
+    })
+}
+
+/// Decode a static (non-animated) image and resize it to fit inside
+/// `width`×`height`.
+///
+/// This is the only place where we decode the pixel data of `source`,
+/// so thumbnails look the same whether we save them or keep them in
+/// memory.  For JPEGs we may decode the image at a reduced size.  If
+/// `use_embedded_preview` is true and the image has a big enough preview
+/// in its EXIF metadata, we resize that instead, and don't decode the
+/// full image at all.
+pub(crate) fn resize_static(
+    source: &SourceImage,
+    width: u32,
+    height: u32,
+    use_embedded_preview: bool,
+) -> Result<DynamicImage, ThumbnailError> {
+    let embedded_preview = if use_embedded_preview {
+        source.decode_embedded_preview(width, height)
+    } else {
+        None
+    };
+
+    let img = match embedded_preview {
+        Some(preview) => preview,
+        None => source.decode_for_thumbnail(width, height)?,
+    };
+
+    Ok(resize_decoded(&img, width, height))
+}
+
+/// Resize an already-decoded image to fit inside `width`×`height`.
+///
+/// Every static thumbnail goes through here, including the stills we
+/// extract from videos, so they're all resized the same way.
+pub(crate) fn resize_decoded(img: &DynamicImage, width: u32, height: u32) -> DynamicImage {
+    img.resize(width, height, FilterType::Lanczos3)
+}
+
+/// Encode an already-resized image in `format`.
+///
+/// The thumbnail only exists in memory, so if this fails, the error
+/// names the original image at `path`.
+pub fn encode_static_thumbnail(
+    resized: &DynamicImage,
+    format: ImageFormat,
+    path: &Path,
+) -> Result<Vec<u8>, ThumbnailError> {
+    let mut bytes = Vec::new();
+    resized
+        .write_to(&mut Cursor::new(&mut bytes), format)
+        .map_err(|source| ThumbnailError::ImageSaveError {
+            path: path.to_path_buf(),
+            source,
+        })?;
+
+    Ok(bytes)
+}
+
+/// Save an already-resized image as a thumbnail in `format`.
+///
+/// The thumbnail is written to a temporary file and then renamed, so
+/// `thumbnail_path` never contains a partially-written image.
+pub fn save_static_thumbnail(
+    resized: &DynamicImage,
+    format: ImageFormat,
+    thumbnail_path: &Path,
+) -> Result<(), ThumbnailError> {
+    let file = AtomicFile::new(thumbnail_path);
+
+    resized
+        .save_with_format(file.temp_path(), format)
+        .map_err(|source| match source {
+            ImageError::IoError(e) => ThumbnailError::io(thumbnail_path, e),
+            source => ThumbnailError::ImageSaveError {
+                path: thumbnail_path.to_path_buf(),
+                source,
+            },
+        })?;
+
+    file.persist()
+        .map_err(|e| ThumbnailError::io(thumbnail_path, e))
+}
+
 #[cfg(test)]
 mod test_create_thumbnail {
-    use std::path::PathBuf;
+    use std::path::{Path, PathBuf};
 
-    use super::create_thumbnail;
+    use std::sync::{Arc, Mutex};
+
+    use image::{DynamicImage, GenericImageView, ImageFormat};
+
+    use super::{
+        content_addressed_name, content_hash, create_thumbnail, create_thumbnail_at,
+        create_thumbnail_from_bytes, create_thumbnail_from_reader, create_thumbnail_in_memory,
+        EncodedThumbnail,
+    };
+    use crate::animated_encoder::{
+        AnimatedEncoder, AnimationInput, AnimationJob, AnimationOutput, EncodedAnimation,
+        FfmpegEncoder, GifThumbnailEncoder,
+    };
+    use crate::errors::ThumbnailError;
+    use crate::ffmpeg::FfmpegConfig;
     use crate::get_thumbnail_dimensions::TargetDimension;
-    use crate::test_utils::{get_dimensions, test_dir};
+    use crate::if_exists::IfExists;
+    use crate::options::ThumbnailOptions;
+    use crate::test_utils::{ffmpeg_is_installed, get_dimensions, test_dir};
+    use crate::video_encoding::{VideoCodec, VideoEncoding};
 
     #[test]
     fn creates_an_animated_gif_thumbnail() {
@@ -49,7 +940,10 @@ mod test_create_thumbnail {
         let out_dir = test_dir();
         let target = TargetDimension::MaxWidth(16);
 
-        let thumbnail_path = create_thumbnail(&gif_path, &out_dir, target).unwrap();
+        let thumbnail_path =
+            create_thumbnail(&gif_path, &out_dir, target, &ThumbnailOptions::default())
+                .unwrap()
+                .path;
 
         assert_eq!(thumbnail_path, out_dir.join("animated_squares.mp4"));
         assert!(thumbnail_path.exists());
@@ -61,10 +955,341 @@ mod test_create_thumbnail {
         let out_dir = test_dir();
         let target = TargetDimension::MaxWidth(15);
 
-        let thumbnail_path = create_thumbnail(&gif_path, &out_dir, target).unwrap();
+        let thumbnail =
+            create_thumbnail(&gif_path, &out_dir, target, &ThumbnailOptions::default()).unwrap();
 
-        assert_eq!(thumbnail_path, out_dir.join("animated_squares.mp4"));
-        assert!(thumbnail_path.exists());
+        assert_eq!(thumbnail.path, out_dir.join("animated_squares.mp4"));
+        assert!(thumbnail.path.exists());
+
+        // ffmpeg needs even dimensions, so the thumbnail gets shrunk
+        // slightly to fit inside the 15px width.
+        assert_eq!((thumbnail.width, thumbnail.height), (14, 14));
+    }
+
+    #[test]
+    fn creates_an_mp4_thumbnail_with_a_background_colour() {
+        if !ffmpeg_is_installed() {
+            return;
+        }
+
+        let gif_path = PathBuf::from("src/tests/transparent_squares.gif");
+        let out_dir = test_dir();
+        let target = TargetDimension::MaxWidth(16);
+        let options = ThumbnailOptions {
+            background: Some("#ffffff".parse().unwrap()),
+            ..Default::default()
+        };
+
+        let thumbnail = create_thumbnail(&gif_path, &out_dir, target, &options).unwrap();
+
+        // Extract the first frame of the MP4 as a PNG, so we can check
+        // the transparent corners were filled in with the background.
+        let png_bytes = FfmpegConfig::default()
+            .run_ffmpeg(
+                &thumbnail.path,
+                &[
+                    "-i".into(),
+                    thumbnail.path.clone().into(),
+                    "-frames:v".into(),
+                    "1".into(),
+                    "-f".into(),
+                    "image2pipe".into(),
+                    "-c:v".into(),
+                    "png".into(),
+                    "-".into(),
+                ],
+            )
+            .unwrap();
+        let first_frame = image::load_from_memory(&png_bytes).unwrap().to_rgb8();
+
+        // MP4 compression is lossy, so we allow a bit of wiggle room.
+        for (x, y) in [(0, 0), (15, 0), (0, 15), (15, 15)] {
+            let pixel = first_frame.get_pixel(x, y).0;
+            assert!(pixel.iter().all(|&c| c > 230), "({x}, {y}) = {pixel:?}");
+        }
+    }
+
+    #[test]
+    fn creates_a_webm_thumbnail_with_vp9() {
+        if !ffmpeg_is_installed() {
+            return;
+        }
+
+        let gif_path = PathBuf::from("src/tests/animated_squares.gif");
+        let out_dir = test_dir();
+        let target = TargetDimension::MaxWidth(16);
+        let options = ThumbnailOptions {
+            animated_encoder: Box::new(FfmpegEncoder {
+                encoding: VideoEncoding {
+                    codec: VideoCodec::Vp9,
+                    ..Default::default()
+                },
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+
+        let thumbnail = create_thumbnail(&gif_path, &out_dir, target, &options).unwrap();
+
+        assert_eq!(thumbnail.path, out_dir.join("animated_squares.webm"));
+        assert!(thumbnail.path.exists());
+        assert!(thumbnail
+            .ffmpeg_args
+            .unwrap()
+            .contains(&"libvpx-vp9".to_string()));
+    }
+
+    #[test]
+    fn creates_an_animated_gif_thumbnail_without_ffmpeg() {
+        let gif_path = PathBuf::from("src/tests/animated_squares.gif");
+        let out_dir = test_dir();
+        let target = TargetDimension::MaxWidth(15);
+        let options = ThumbnailOptions {
+            animated_encoder: Box::new(GifThumbnailEncoder),
+            ..Default::default()
+        };
+
+        let thumbnail = create_thumbnail(&gif_path, &out_dir, target, &options).unwrap();
+
+        assert_eq!(thumbnail.path, out_dir.join("animated_squares.gif"));
+        assert_eq!(get_dimensions(&thumbnail.path), (15, 15));
+    }
+
+    /// An encoder which always fails, like ffmpeg does if it's not installed.
+    #[derive(Debug)]
+    struct BrokenEncoder;
+
+    impl AnimatedEncoder for BrokenEncoder {
+        fn extension(&self) -> &str {
+            "mp4"
+        }
+
+        fn settings(&self) -> serde_json::Value {
+            serde_json::Value::Null
+        }
+
+        fn encode(&self, job: &AnimationJob) -> Result<EncodedAnimation, ThumbnailError> {
+            Err(ThumbnailError::FfmpegNotFound {
+                path: job.input.path().to_path_buf(),
+                program: PathBuf::from("ffmpeg"),
+            })
+        }
+    }
+
+    #[test]
+    fn it_returns_an_error_if_the_encoder_fails() {
+        let gif_path = PathBuf::from("src/tests/animated_squares.gif");
+        let out_dir = test_dir();
+        let target = TargetDimension::MaxWidth(16);
+        let options = ThumbnailOptions {
+            animated_encoder: Box::new(BrokenEncoder),
+            ..Default::default()
+        };
+
+        let result = create_thumbnail(&gif_path, &out_dir, target, &options);
+
+        assert!(matches!(result, Err(ThumbnailError::FfmpegNotFound { .. })));
+    }
+
+    /// An encoder which writes half a file and then fails, like ffmpeg
+    /// does if it crashes partway through.
+    #[derive(Debug)]
+    struct CrashingEncoder;
+
+    impl AnimatedEncoder for CrashingEncoder {
+        fn extension(&self) -> &str {
+            "mp4"
+        }
+
+        fn settings(&self) -> serde_json::Value {
+            serde_json::Value::Null
+        }
+
+        fn encode(&self, job: &AnimationJob) -> Result<EncodedAnimation, ThumbnailError> {
+            if let AnimationOutput::Path(output) = job.output {
+                std::fs::write(output, "half an mp4").unwrap();
+            }
+
+            Err(ThumbnailError::EncodeFailed {
+                path: job.input.path().to_path_buf(),
+                message: "ffmpeg crashed".to_string(),
+            })
+        }
+    }
+
+    #[test]
+    fn it_doesnt_leave_a_partial_thumbnail_if_the_encoder_fails() {
+        let gif_path = PathBuf::from("src/tests/animated_squares.gif");
+        let out_dir = test_dir();
+        let target = TargetDimension::MaxWidth(16);
+        let options = ThumbnailOptions {
+            animated_encoder: Box::new(CrashingEncoder),
+            ..Default::default()
+        };
+
+        let result = create_thumbnail(&gif_path, &out_dir, target, &options);
+
+        assert!(result.is_err());
+        assert_eq!(std::fs::read_dir(&out_dir).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn it_only_leaves_the_thumbnail_in_the_out_dir() {
+        let img_path = PathBuf::from("src/tests/red.png");
+        let out_dir = test_dir();
+        let target = TargetDimension::MaxWidth(16);
+
+        let thumbnail =
+            create_thumbnail(&img_path, &out_dir, target, &ThumbnailOptions::default()).unwrap();
+
+        let entries: Vec<PathBuf> = std::fs::read_dir(&out_dir)
+            .unwrap()
+            .map(|e| e.unwrap().path())
+            .collect();
+        assert_eq!(entries, vec![thumbnail.path]);
+    }
+
+    /// The (input, output, width, height) of an encoding job.
+    type RecordedJob = (PathBuf, PathBuf, u32, u32);
+
+    /// An encoder which records the jobs it's asked to do, and creates
+    /// an empty file instead of a real animation.
+    #[derive(Debug, Default)]
+    struct RecordingEncoder {
+        jobs: Arc<Mutex<Vec<RecordedJob>>>,
+
+        /// How long to take over each job, like a real encoder would
+        delay: std::time::Duration,
+    }
+
+    impl AnimatedEncoder for RecordingEncoder {
+        fn extension(&self) -> &str {
+            "webm"
+        }
+
+        // The jobs we've recorded don't affect the thumbnail.
+        fn settings(&self) -> serde_json::Value {
+            serde_json::json!({ "encoder": "recording" })
+        }
+
+        fn encode(&self, job: &AnimationJob) -> Result<EncodedAnimation, ThumbnailError> {
+            std::thread::sleep(self.delay);
+
+            if let (AnimationInput::Path(input), AnimationOutput::Path(output)) =
+                (&job.input, &job.output)
+            {
+                self.jobs.lock().unwrap().push((
+                    input.to_path_buf(),
+                    output.to_path_buf(),
+                    job.width,
+                    job.height,
+                ));
+            }
+
+            if let AnimationOutput::Path(output) = job.output {
+                std::fs::write(output, "").unwrap();
+            }
+
+            Ok(EncodedAnimation {
+                width: job.width,
+                height: job.height,
+                ffmpeg_args: None,
+                bytes: match job.output {
+                    AnimationOutput::Path(_) => None,
+                    AnimationOutput::Memory => Some(vec![]),
+                },
+            })
+        }
+    }
+
+    #[test]
+    fn it_passes_the_thumbnail_dimensions_to_the_encoder() {
+        let gif_path = PathBuf::from("src/tests/animated_squares.gif");
+        let out_dir = test_dir();
+        let target = TargetDimension::MaxWidth(15);
+        let encoder = RecordingEncoder::default();
+        let jobs = Arc::clone(&encoder.jobs);
+        let options = ThumbnailOptions {
+            animated_encoder: Box::new(encoder),
+            ..Default::default()
+        };
+
+        let thumbnail = create_thumbnail(&gif_path, &out_dir, target, &options).unwrap();
+
+        assert_eq!(thumbnail.path, out_dir.join("animated_squares.webm"));
+        assert!(thumbnail.path.exists());
+
+        // The encoder writes to a temporary file next to the thumbnail,
+        // which is then renamed into place.
+        let jobs = jobs.lock().unwrap();
+        assert_eq!(jobs.len(), 1);
+
+        let (input, output, width, height) = &jobs[0];
+        assert_eq!(input, &gif_path);
+        assert_eq!(output.parent(), Some(out_dir.as_path()));
+        assert_eq!(output.extension().unwrap(), "webm");
+        assert!(!output.exists());
+        assert_eq!((*width, *height), (15, 15));
+    }
+
+    #[test]
+    fn it_doesnt_encode_a_thumbnail_it_skips() {
+        let gif_path = PathBuf::from("src/tests/animated_squares.gif");
+        let out_dir = test_dir();
+        std::fs::create_dir_all(&out_dir).unwrap();
+        std::fs::write(out_dir.join("animated_squares.webm"), "").unwrap();
+
+        let encoder = RecordingEncoder::default();
+        let jobs = Arc::clone(&encoder.jobs);
+        let options = ThumbnailOptions {
+            animated_encoder: Box::new(encoder),
+            if_exists: IfExists::Skip,
+            ..Default::default()
+        };
+
+        let target = TargetDimension::MaxWidth(15);
+        let thumbnail = create_thumbnail(&gif_path, &out_dir, target, &options).unwrap();
+
+        assert!(thumbnail.skipped);
+        assert_eq!(thumbnail.path, out_dir.join("animated_squares.webm"));
+        assert_eq!((thumbnail.width, thumbnail.height), (15, 15));
+        assert_eq!(*jobs.lock().unwrap(), vec![]);
+    }
+
+    #[test]
+    fn concurrent_calls_reuse_the_same_content_addressed_thumbnail() {
+        let gif_path = PathBuf::from("src/tests/animated_squares.gif");
+        let out_dir = test_dir();
+        let target = TargetDimension::MaxWidth(15);
+
+        let jobs = Arc::new(Mutex::new(vec![]));
+
+        // Each thread has its own options, but they share a record of the
+        // encoding jobs, so we can see how many thumbnails were created.
+        let create = || {
+            let options = ThumbnailOptions {
+                animated_encoder: Box::new(RecordingEncoder {
+                    jobs: Arc::clone(&jobs),
+                    delay: std::time::Duration::from_millis(100),
+                }),
+                content_addressed: true,
+                ..Default::default()
+            };
+
+            create_thumbnail(&gif_path, &out_dir, target, &options)
+        };
+
+        let paths: Vec<PathBuf> = std::thread::scope(|s| {
+            let handles: Vec<_> = (0..4).map(|_| s.spawn(create)).collect();
+
+            handles
+                .into_iter()
+                .map(|h| h.join().unwrap().unwrap().path)
+                .collect()
+        });
+
+        assert!(paths.iter().all(|p| p == &paths[0]));
+        assert_eq!(jobs.lock().unwrap().len(), 1);
     }
 
     #[test]
@@ -73,7 +1298,10 @@ mod test_create_thumbnail {
         let out_dir = test_dir();
         let target = TargetDimension::MaxWidth(16);
 
-        let thumbnail_path = create_thumbnail(&img_path, &out_dir, target).unwrap();
+        let thumbnail_path =
+            create_thumbnail(&img_path, &out_dir, target, &ThumbnailOptions::default())
+                .unwrap()
+                .path;
 
         assert_eq!(thumbnail_path, out_dir.join("yellow.gif"));
         assert!(thumbnail_path.exists());
@@ -86,20 +1314,39 @@ mod test_create_thumbnail {
         let out_dir = test_dir();
         let target = TargetDimension::MaxWidth(16);
 
-        let thumbnail_path = create_thumbnail(&img_path, &out_dir, target).unwrap();
+        let thumbnail_path =
+            create_thumbnail(&img_path, &out_dir, target, &ThumbnailOptions::default())
+                .unwrap()
+                .path;
 
         assert_eq!(thumbnail_path, out_dir.join("red.png"));
         assert!(thumbnail_path.exists());
         assert_eq!(get_dimensions(&thumbnail_path), (16, 32));
     }
 
+    #[test]
+    fn it_reports_the_dimensions_of_the_thumbnail() {
+        let img_path = PathBuf::from("src/tests/red.png");
+        let out_dir = test_dir();
+        let target = TargetDimension::MaxWidth(16);
+
+        let thumbnail =
+            create_thumbnail(&img_path, &out_dir, target, &ThumbnailOptions::default()).unwrap();
+
+        assert_eq!((thumbnail.width, thumbnail.height), (16, 32));
+        assert_eq!(thumbnail.ffmpeg_args, None);
+    }
+
     #[test]
     fn creates_a_jpeg_thumbnail() {
         let img_path = PathBuf::from("src/tests/noise.jpg");
         let out_dir = test_dir();
         let target = TargetDimension::MaxWidth(16);
 
-        let thumbnail_path = create_thumbnail(&img_path, &out_dir, target).unwrap();
+        let thumbnail_path =
+            create_thumbnail(&img_path, &out_dir, target, &ThumbnailOptions::default())
+                .unwrap()
+                .path;
 
         assert_eq!(thumbnail_path, out_dir.join("noise.jpg"));
         assert!(thumbnail_path.exists());
@@ -112,7 +1359,10 @@ mod test_create_thumbnail {
         let out_dir = test_dir();
         let target = TargetDimension::MaxHeight(16);
 
-        let thumbnail_path = create_thumbnail(&img_path, &out_dir, target).unwrap();
+        let thumbnail_path =
+            create_thumbnail(&img_path, &out_dir, target, &ThumbnailOptions::default())
+                .unwrap()
+                .path;
 
         assert_eq!(thumbnail_path, out_dir.join("green.tiff"));
         assert!(thumbnail_path.exists());
@@ -125,7 +1375,10 @@ mod test_create_thumbnail {
         let out_dir = test_dir();
         let target = TargetDimension::MaxWidth(16);
 
-        let thumbnail_path = create_thumbnail(&img_path, &out_dir, target).unwrap();
+        let thumbnail_path =
+            create_thumbnail(&img_path, &out_dir, target, &ThumbnailOptions::default())
+                .unwrap()
+                .path;
 
         assert_eq!(thumbnail_path, out_dir.join("purple.webp"));
         assert!(thumbnail_path.exists());
@@ -138,7 +1391,10 @@ mod test_create_thumbnail {
         let out_dir = test_dir();
         let target = TargetDimension::MaxWidth(500);
 
-        let thumbnail_path = create_thumbnail(&img_path, &out_dir, target).unwrap();
+        let thumbnail_path =
+            create_thumbnail(&img_path, &out_dir, target, &ThumbnailOptions::default())
+                .unwrap()
+                .path;
 
         assert_eq!(thumbnail_path, out_dir.join("noise.jpg"));
         assert!(thumbnail_path.exists());
@@ -154,110 +1410,619 @@ mod test_create_thumbnail {
         let out_dir = test_dir();
         let target = TargetDimension::MaxWidth(180);
 
-        let thumbnail_path = create_thumbnail(&img_path, &out_dir, target).unwrap();
+        let thumbnail_path =
+            create_thumbnail(&img_path, &out_dir, target, &ThumbnailOptions::default())
+                .unwrap()
+                .path;
 
         assert_eq!(thumbnail_path, out_dir.join("Landscape_5.jpg"));
         assert!(thumbnail_path.exists());
         assert_eq!(get_dimensions(&thumbnail_path), (180, 120));
     }
-}
 
-/// Return this value if it's even, or the closest value which is even.
-fn ensure_even(x: u32) -> u32 {
-    if x % 2 == 0 {
-        x
-    } else {
-        x + 1
+    #[test]
+    fn it_applies_exif_orientation_to_bytes() {
+        let bytes = include_bytes!("tests/Landscape_5.jpg");
+        let target = TargetDimension::MaxWidth(180);
+
+        let thumbnail =
+            create_thumbnail_from_bytes(bytes, target, None, &ThumbnailOptions::default()).unwrap();
+
+        assert_eq!((thumbnail.width, thumbnail.height), (180, 120));
+        assert_eq!(decode(&thumbnail).dimensions(), (180, 120));
     }
-}
 
-/// Create a thumbnail for an animated GIF.
-///
-/// This will use `ffmpeg` to create an MP4 file of the desired dimensions
-/// which plays the GIF on a loop.  This is typically much smaller and more
-/// space-efficient than creating a resized GIF.
-///
-/// This function assumes that the original GIF file definitely exists.
-///
-/// TODO: It would be nice to have a test for the case where `ffmpeg` isn't
-/// installed, but I'm not sure how to simulate that.
-///
-pub fn create_animated_gif_thumbnail(
-    gif_path: &PathBuf,
-    out_dir: &PathBuf,
-    width: u32,
-    height: u32,
-) -> Result<PathBuf, ThumbnailError> {
-    let file_name = gif_path
-        .file_name()
-        .ok_or(ThumbnailError::MissingFileName)?;
-
-    let thumbnail_path = out_dir.join(file_name).with_extension("mp4");
-
-    let gif_path_str = gif_path
-        .to_str()
-        .ok_or(ThumbnailError::PathConversionError)?;
-    let thumbnail_path_str = thumbnail_path
-        .to_str()
-        .ok_or(ThumbnailError::PathConversionError)?;
-
-    // There's a subtlety here with ffmpeg I don't understand fully -- if
-    // the width/height aren't even, it doesn't create the MP4, instead
-    // failing with the error:
-    //
-    //     width not divisible by 2
-    //
-    // I don't usually need these files to be pixel-perfect width, so
-    // fudging by a single pixel or two is fine.
-    let dimension_str = format!("scale={}:{}", ensure_even(width), ensure_even(height));
-
-    let output = Command::new("ffmpeg")
-        .args([
-            "-i",
-            gif_path_str,
-            "-movflags",
-            "faststart",
-            "-pix_fmt",
-            "yuv420p",
-            "-vf",
-            &dimension_str,
-            thumbnail_path_str,
-        ])
-        .output()
-        .map_err(|e| ThumbnailError::CommandFailed(format!("Failed to run ffmpeg: {}", e)))?;
-
-    if output.status.success() {
-        Ok(thumbnail_path)
-    } else {
-        let stderr = str::from_utf8(&output.stderr)?;
-        Err(ThumbnailError::CommandFailed(stderr.to_string()))
+    #[test]
+    fn it_uses_the_embedded_preview_if_asked() {
+        // The main image is red, and the embedded preview is blue.
+        let bytes = include_bytes!("tests/embedded_preview.jpg");
+        let target = TargetDimension::MaxWidth(90);
+        let options = ThumbnailOptions {
+            use_embedded_preview: true,
+            ..Default::default()
+        };
+
+        let thumbnail = create_thumbnail_from_bytes(bytes, target, None, &options).unwrap();
+
+        assert_eq!((thumbnail.width, thumbnail.height), (90, 120));
+
+        let img = decode(&thumbnail).to_rgb8();
+        let [r, _, b] = img.get_pixel(45, 60).0;
+        assert!(b > 200 && r < 50, "{:?}", img.get_pixel(45, 60));
     }
-}
 
-/// Create a thumbnail for a static (non-animated) image.
-///
-/// This function assumes that the original image file definitely exists.
-///
-pub fn create_static_thumbnail(
-    image_path: &PathBuf,
-    out_dir: &PathBuf,
-    width: u32,
-    height: u32,
-) -> Result<PathBuf, ThumbnailError> {
-    let file_name = image_path
-        .file_name()
-        .ok_or(ThumbnailError::MissingFileName)?;
+    #[test]
+    fn it_ignores_the_embedded_preview_by_default() {
+        let bytes = include_bytes!("tests/embedded_preview.jpg");
+        let target = TargetDimension::MaxWidth(90);
 
-    let thumbnail_path = out_dir.join(file_name);
+        let thumbnail =
+            create_thumbnail_from_bytes(bytes, target, None, &ThumbnailOptions::default()).unwrap();
 
-    let mut decoder = ImageReader::open(image_path)?.into_decoder()?;
-    let orientation = decoder.orientation()?;
-    let mut img = DynamicImage::from_decoder(decoder)?;
-    img.apply_orientation(orientation);
+        assert_eq!((thumbnail.width, thumbnail.height), (90, 120));
 
-    img.resize(width, height, FilterType::Lanczos3)
-        .save(&thumbnail_path)
-        .map_err(ThumbnailError::ImageSaveError)?;
+        let img = decode(&thumbnail).to_rgb8();
+        let [r, _, b] = img.get_pixel(45, 60).0;
+        assert!(r > 200 && b < 50, "{:?}", img.get_pixel(45, 60));
+    }
+
+    #[test]
+    fn it_falls_back_to_the_full_image_if_the_preview_is_too_small() {
+        let bytes = include_bytes!("tests/embedded_preview.jpg");
+        let target = TargetDimension::MaxWidth(240);
+        let options = ThumbnailOptions {
+            use_embedded_preview: true,
+            ..Default::default()
+        };
+
+        let thumbnail = create_thumbnail_from_bytes(bytes, target, None, &options).unwrap();
+
+        assert_eq!((thumbnail.width, thumbnail.height), (240, 320));
+
+        let img = decode(&thumbnail).to_rgb8();
+        let [r, _, b] = img.get_pixel(120, 160).0;
+        assert!(r > 200 && b < 50, "{:?}", img.get_pixel(120, 160));
+    }
+
+    #[test]
+    fn it_names_the_thumbnail_after_the_real_format() {
+        let tmp_dir = test_dir();
+        std::fs::create_dir_all(&tmp_dir).unwrap();
+        let img_path = tmp_dir.join("red.jpg");
+        std::fs::copy("src/tests/red.png", &img_path).unwrap();
+
+        let out_dir = tmp_dir.join("thumbnails");
+        let target = TargetDimension::MaxWidth(50);
+
+        let thumbnail =
+            create_thumbnail(&img_path, &out_dir, target, &ThumbnailOptions::default()).unwrap();
+
+        assert_eq!(thumbnail.path, out_dir.join("red.png"));
+        assert_eq!(
+            image::ImageFormat::from_path(&thumbnail.path).unwrap(),
+            image::guess_format(&std::fs::read(&thumbnail.path).unwrap()).unwrap()
+        );
+        assert_eq!(
+            thumbnail.warnings,
+            vec![format!(
+                "{} looks like a PNG image, but has the extension .jpg",
+                img_path.display()
+            )]
+        );
+    }
+
+    #[test]
+    fn it_adds_an_extension_if_the_image_does_not_have_one() {
+        let tmp_dir = test_dir();
+        std::fs::create_dir_all(&tmp_dir).unwrap();
+        let img_path = tmp_dir.join("red");
+        std::fs::copy("src/tests/red.png", &img_path).unwrap();
+
+        let out_dir = tmp_dir.join("thumbnails");
+        let target = TargetDimension::MaxWidth(50);
+
+        let thumbnail =
+            create_thumbnail(&img_path, &out_dir, target, &ThumbnailOptions::default()).unwrap();
+
+        assert_eq!(thumbnail.path, out_dir.join("red.png"));
+        assert_eq!(get_dimensions(&thumbnail.path), (50, 100));
+        assert!(thumbnail.warnings.is_empty());
+    }
+
+    #[test]
+    fn it_does_not_warn_about_a_jpeg_extension() {
+        let tmp_dir = test_dir();
+        std::fs::create_dir_all(&tmp_dir).unwrap();
+        let img_path = tmp_dir.join("noise.jpeg");
+        std::fs::copy("src/tests/noise.jpg", &img_path).unwrap();
+
+        let out_dir = tmp_dir.join("thumbnails");
+        let target = TargetDimension::MaxWidth(50);
+
+        let thumbnail =
+            create_thumbnail(&img_path, &out_dir, target, &ThumbnailOptions::default()).unwrap();
+
+        assert_eq!(thumbnail.path, out_dir.join("noise.jpeg"));
+        assert!(thumbnail.warnings.is_empty());
+    }
+
+    #[test]
+    fn it_creates_a_thumbnail_in_memory() {
+        let img_path = PathBuf::from("src/tests/red.png");
+        let target = TargetDimension::MaxWidth(50);
+
+        let thumbnail =
+            create_thumbnail_in_memory(&img_path, target, None, &ThumbnailOptions::default())
+                .unwrap();
+
+        assert_eq!(
+            image::guess_format(&thumbnail.bytes).unwrap(),
+            ImageFormat::Png
+        );
+        assert_eq!(
+            image::load_from_memory(&thumbnail.bytes)
+                .unwrap()
+                .dimensions(),
+            (50, 100)
+        );
+    }
+
+    #[test]
+    fn it_creates_a_thumbnail_in_memory_in_a_different_format() {
+        let img_path = PathBuf::from("src/tests/red.png");
+        let target = TargetDimension::MaxWidth(50);
+
+        let thumbnail = create_thumbnail_in_memory(
+            &img_path,
+            target,
+            Some(ImageFormat::Jpeg),
+            &ThumbnailOptions::default(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            image::guess_format(&thumbnail.bytes).unwrap(),
+            ImageFormat::Jpeg
+        );
+    }
+
+    #[test]
+    fn it_creates_an_animated_thumbnail_in_memory() {
+        let gif_path = PathBuf::from("src/tests/animated_squares.gif");
+        let target = TargetDimension::MaxWidth(15);
+        let options = ThumbnailOptions {
+            animated_encoder: Box::new(GifThumbnailEncoder),
+            ..Default::default()
+        };
+
+        let thumbnail = create_thumbnail_in_memory(&gif_path, target, None, &options).unwrap();
+
+        assert_eq!(
+            image::guess_format(&thumbnail.bytes).unwrap(),
+            ImageFormat::Gif
+        );
+        assert_eq!(
+            image::load_from_memory(&thumbnail.bytes)
+                .unwrap()
+                .dimensions(),
+            (15, 15)
+        );
+    }
 
-    Ok(thumbnail_path)
+    #[test]
+    fn it_does_not_create_video_thumbnails_in_memory() {
+        let video_path = PathBuf::from("src/tests/bars.avi");
+        let target = TargetDimension::MaxWidth(16);
+
+        let result =
+            create_thumbnail_in_memory(&video_path, target, None, &ThumbnailOptions::default());
+
+        assert!(matches!(result, Err(ThumbnailError::InvalidOptions(_))));
+    }
+
+    /// Decode an image thumbnail which has been created in memory.
+    fn decode(thumbnail: &EncodedThumbnail) -> DynamicImage {
+        image::load_from_memory(&thumbnail.bytes).unwrap()
+    }
+
+    #[test]
+    fn it_creates_a_thumbnail_from_bytes() {
+        let bytes = include_bytes!("tests/red.png");
+        let target = TargetDimension::MaxWidth(16);
+
+        let thumbnail =
+            create_thumbnail_from_bytes(bytes, target, None, &ThumbnailOptions::default()).unwrap();
+
+        assert_eq!((thumbnail.width, thumbnail.height), (16, 32));
+        assert_eq!(thumbnail.extension, "png");
+        assert!(!thumbnail.is_animated);
+        assert_eq!(thumbnail.ffmpeg_args, None);
+        assert_eq!(
+            image::guess_format(&thumbnail.bytes).unwrap(),
+            ImageFormat::Png
+        );
+        assert_eq!(decode(&thumbnail).dimensions(), (16, 32));
+    }
+
+    #[test]
+    fn it_creates_thumbnails_from_bytes_in_every_format() {
+        let images: [(&[u8], ImageFormat, (u32, u32)); 5] = [
+            (
+                include_bytes!("tests/yellow.gif"),
+                ImageFormat::Gif,
+                (16, 8),
+            ),
+            (include_bytes!("tests/red.png"), ImageFormat::Png, (16, 32)),
+            (
+                include_bytes!("tests/noise.jpg"),
+                ImageFormat::Jpeg,
+                (16, 32),
+            ),
+            (
+                include_bytes!("tests/green.tiff"),
+                ImageFormat::Tiff,
+                (16, 16),
+            ),
+            (
+                include_bytes!("tests/purple.webp"),
+                ImageFormat::WebP,
+                (16, 16),
+            ),
+        ];
+
+        for (bytes, format, dimensions) in images {
+            let target = TargetDimension::MaxWidth(16);
+
+            let thumbnail =
+                create_thumbnail_from_bytes(bytes, target, None, &ThumbnailOptions::default())
+                    .unwrap();
+
+            assert_eq!(image::guess_format(&thumbnail.bytes).unwrap(), format);
+            assert_eq!(decode(&thumbnail).dimensions(), dimensions, "{format:?}");
+        }
+    }
+
+    #[test]
+    fn it_creates_an_animated_thumbnail_from_a_reader() {
+        let file = std::fs::File::open("src/tests/animated_squares.gif").unwrap();
+        let target = TargetDimension::MaxWidth(15);
+        let encoder = RecordingEncoder::default();
+        let options = ThumbnailOptions {
+            animated_encoder: Box::new(encoder),
+            ..Default::default()
+        };
+
+        let thumbnail = create_thumbnail_from_reader(file, target, None, &options).unwrap();
+
+        assert_eq!((thumbnail.width, thumbnail.height), (15, 15));
+        assert_eq!(thumbnail.extension, "webm");
+        assert!(thumbnail.is_animated);
+    }
+
+    #[test]
+    fn it_rejects_bytes_which_are_not_an_image() {
+        let target = TargetDimension::MaxWidth(16);
+
+        let result = create_thumbnail_from_bytes(
+            b"not an image",
+            target,
+            None,
+            &ThumbnailOptions::default(),
+        );
+
+        assert!(matches!(
+            result,
+            Err(ThumbnailError::UnsupportedFormat { .. })
+        ));
+    }
+
+    #[test]
+    fn it_saves_a_thumbnail_at_an_exact_path() {
+        let img_path = PathBuf::from("src/tests/noise.jpg");
+        let thumbnail_path = test_dir().join("thumbnail.png");
+        let target = TargetDimension::MaxWidth(16);
+
+        let thumbnail = create_thumbnail_at(
+            &img_path,
+            &thumbnail_path,
+            target,
+            None,
+            &ThumbnailOptions::default(),
+        )
+        .unwrap();
+
+        assert_eq!(thumbnail.path, thumbnail_path);
+        assert_eq!(get_dimensions(&thumbnail_path), (16, 32));
+        assert_eq!(
+            image::guess_format(&std::fs::read(&thumbnail_path).unwrap()).unwrap(),
+            ImageFormat::Png
+        );
+    }
+
+    #[test]
+    fn it_uses_the_format_if_given() {
+        let img_path = PathBuf::from("src/tests/noise.jpg");
+        let thumbnail_path = test_dir().join("thumbnail.img");
+        let target = TargetDimension::MaxWidth(16);
+
+        create_thumbnail_at(
+            &img_path,
+            &thumbnail_path,
+            target,
+            Some(ImageFormat::Png),
+            &ThumbnailOptions::default(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            image::guess_format(&std::fs::read(&thumbnail_path).unwrap()).unwrap(),
+            ImageFormat::Png
+        );
+    }
+
+    #[test]
+    fn an_animated_thumbnail_needs_the_encoder_extension() {
+        let gif_path = PathBuf::from("src/tests/animated_squares.gif");
+        let thumbnail_path = test_dir().join("thumbnail.gif");
+        let target = TargetDimension::MaxWidth(16);
+
+        let result = create_thumbnail_at(
+            &gif_path,
+            &thumbnail_path,
+            target,
+            None,
+            &ThumbnailOptions::default(),
+        );
+
+        assert!(matches!(result, Err(ThumbnailError::InvalidOptions(_))));
+        assert!(!thumbnail_path.exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn it_will_not_overwrite_the_original_through_a_symlink() {
+        let tmp_dir = test_dir();
+        std::fs::create_dir_all(&tmp_dir).unwrap();
+        let img_path = tmp_dir.join("red.png");
+        std::fs::copy("src/tests/red.png", &img_path).unwrap();
+        let link_path = tmp_dir.join("link.png");
+        std::os::unix::fs::symlink(&img_path, &link_path).unwrap();
+
+        let result = create_thumbnail_at(
+            &img_path,
+            &link_path,
+            TargetDimension::MaxWidth(16),
+            None,
+            &ThumbnailOptions::default(),
+        );
+
+        assert!(matches!(
+            result,
+            Err(ThumbnailError::SameInputOutputPath(_))
+        ));
+        assert_eq!(get_dimensions(&img_path), (100, 200));
+    }
+
+    #[test]
+    fn it_names_the_thumbnail_with_a_template() {
+        let img_path = PathBuf::from("src/tests/red.png");
+        let out_dir = test_dir();
+        let target = TargetDimension::MaxWidth(16);
+        let options = ThumbnailOptions {
+            name_template: Some("{stem}_{width}x{height}_{target}.{ext}".parse().unwrap()),
+            ..Default::default()
+        };
+
+        let thumbnail = create_thumbnail(&img_path, &out_dir, target, &options).unwrap();
+
+        assert_eq!(thumbnail.path, out_dir.join("red_16x32_w16.png"));
+        assert_eq!(get_dimensions(&thumbnail.path), (16, 32));
+    }
+
+    #[test]
+    fn it_names_the_thumbnail_after_its_contents() {
+        let out_dir = test_dir();
+        let target = TargetDimension::MaxWidth(16);
+        let options = ThumbnailOptions {
+            name_template: Some("{hash8}.{ext}".parse().unwrap()),
+            ..Default::default()
+        };
+
+        let thumbnail =
+            create_thumbnail(Path::new("src/tests/red.png"), &out_dir, target, &options).unwrap();
+
+        let hash = content_hash(std::fs::File::open("src/tests/red.png").unwrap()).unwrap();
+        assert_eq!(thumbnail.path, out_dir.join(format!("{}.png", &hash[..8])));
+    }
+
+    #[test]
+    fn it_names_an_animated_thumbnail_with_a_template() {
+        let gif_path = PathBuf::from("src/tests/animated_squares.gif");
+        let out_dir = test_dir();
+        let target = TargetDimension::MaxWidth(15);
+        let options = ThumbnailOptions {
+            animated_encoder: Box::new(RecordingEncoder::default()),
+            name_template: Some("{dir}/{stem}_{width}.{ext}".parse().unwrap()),
+            ..Default::default()
+        };
+
+        let thumbnail = create_thumbnail(&gif_path, &out_dir, target, &options).unwrap();
+
+        assert_eq!(
+            thumbnail.path,
+            out_dir.join("src/tests/animated_squares_15.webm")
+        );
+    }
+
+    #[test]
+    fn the_template_uses_the_dimensions_from_the_encoder() {
+        // ffmpeg needs even dimensions, so a 15px wide GIF becomes 14px.
+        let encoder = FfmpegEncoder::default();
+
+        assert_eq!(encoder.output_dimensions(15, 15), (14, 14));
+        assert_eq!(GifThumbnailEncoder.output_dimensions(15, 15), (15, 15));
+    }
+
+    #[test]
+    fn it_stores_thumbnails_by_content() {
+        let tmp_dir = test_dir();
+        std::fs::create_dir_all(&tmp_dir).unwrap();
+        let copy_path = tmp_dir.join("another_red.png");
+        std::fs::copy("src/tests/red.png", &copy_path).unwrap();
+
+        let out_dir = tmp_dir.join("thumbnails");
+        let target = TargetDimension::MaxWidth(16);
+        let options = ThumbnailOptions {
+            content_addressed: true,
+            ..Default::default()
+        };
+
+        let thumbnail =
+            create_thumbnail(Path::new("src/tests/red.png"), &out_dir, target, &options).unwrap();
+
+        // The thumbnail is stored as `out_dir/ab/cdef….png`
+        let name = thumbnail.path.strip_prefix(&out_dir).unwrap();
+        let components: Vec<_> = name.iter().map(|c| c.to_str().unwrap()).collect();
+        assert_eq!(components.len(), 2);
+        assert_eq!(components[0].len(), 2);
+        assert_eq!(components[1].len(), 62 + ".png".len());
+        assert_eq!(get_dimensions(&thumbnail.path), (16, 32));
+
+        // An identical file gets the same thumbnail, which we reuse
+        // rather than creating again.
+        std::fs::write(&thumbnail.path, "this was already here").unwrap();
+
+        let duplicate = create_thumbnail(&copy_path, &out_dir, target, &options).unwrap();
+
+        assert_eq!(duplicate.path, thumbnail.path);
+        assert_eq!((duplicate.width, duplicate.height), (16, 32));
+        assert_eq!(
+            std::fs::read_to_string(&duplicate.path).unwrap(),
+            "this was already here"
+        );
+    }
+
+    #[test]
+    fn content_addressed_names_are_stable() {
+        // If this changes, existing content-addressed thumbnails won't be
+        // reused -- if that's deliberate, change the version in ContentKey.
+        let name = content_addressed_name(
+            "hello world".as_bytes(),
+            "png",
+            (16, 32),
+            TargetDimension::MaxWidth(16),
+            &ThumbnailOptions::default(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            name,
+            PathBuf::from("ca/89877c6fa0e7e2c537cbefd58714cdd7d1efcea4d22c4f618297a3a930eb90.png")
+        );
+    }
+
+    #[test]
+    fn content_addressed_names_depend_on_the_options() {
+        let img_path = PathBuf::from("src/tests/red.png");
+        let out_dir = test_dir();
+        let options = ThumbnailOptions {
+            content_addressed: true,
+            ..Default::default()
+        };
+
+        let small =
+            create_thumbnail(&img_path, &out_dir, TargetDimension::MaxWidth(16), &options).unwrap();
+        let large =
+            create_thumbnail(&img_path, &out_dir, TargetDimension::MaxWidth(32), &options).unwrap();
+        let other_image = create_thumbnail(
+            Path::new("src/tests/blue.png"),
+            &out_dir,
+            TargetDimension::MaxWidth(16),
+            &options,
+        )
+        .unwrap();
+
+        assert_ne!(small.path, large.path);
+        assert_ne!(small.path, other_image.path);
+    }
+
+    /// Copy one of the test images to a new directory, under a name
+    /// which is `cät` in Latin-1, and so isn't valid UTF-8.
+    #[cfg(target_os = "linux")]
+    fn copy_with_non_utf8_name(path: &str) -> PathBuf {
+        use std::ffi::OsString;
+        use std::os::unix::ffi::OsStringExt;
+
+        let dir = test_dir();
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let mut name = OsString::from_vec(b"c\xe4t.".to_vec());
+        name.push(Path::new(path).extension().unwrap());
+
+        let new_path = dir.join(name);
+        std::fs::copy(path, &new_path).unwrap();
+        new_path
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn creates_a_thumbnail_for_a_file_whose_name_isnt_utf8() {
+        let img_path = copy_with_non_utf8_name("src/tests/red.png");
+        let out_dir = test_dir();
+
+        let thumbnail = create_thumbnail(
+            &img_path,
+            &out_dir,
+            TargetDimension::MaxWidth(50),
+            &ThumbnailOptions::default(),
+        )
+        .unwrap();
+
+        assert_eq!(thumbnail.path, out_dir.join(img_path.file_name().unwrap()));
+        assert_eq!(get_dimensions(&thumbnail.path), (50, 100));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn creates_an_animated_thumbnail_for_a_file_whose_name_isnt_utf8() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let gif_path = copy_with_non_utf8_name("src/tests/animated_squares.gif");
+        let out_dir = test_dir();
+
+        // This fake ffmpeg copies its input to its output, so we can
+        // check the paths reach ffmpeg intact.
+        let dir = test_dir();
+        std::fs::create_dir_all(&dir).unwrap();
+        let fake_ffmpeg = dir.join("ffmpeg");
+        std::fs::write(
+            &fake_ffmpeg,
+            "#!/bin/sh\nwhile [ \"$1\" != -i ]; do shift; done\ninput=\"$2\"\nfor last; do :; done\ncp \"$input\" \"$last\"\n",
+        )
+        .unwrap();
+        std::fs::set_permissions(&fake_ffmpeg, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let options = ThumbnailOptions {
+            animated_encoder: Box::new(FfmpegEncoder {
+                ffmpeg: FfmpegConfig {
+                    ffmpeg: fake_ffmpeg,
+                    ..Default::default()
+                },
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+
+        let thumbnail =
+            create_thumbnail(&gif_path, &out_dir, TargetDimension::MaxWidth(16), &options).unwrap();
+
+        assert_eq!(
+            thumbnail.path,
+            out_dir.join(gif_path.with_extension("mp4").file_name().unwrap())
+        );
+        assert_eq!(
+            std::fs::read(&thumbnail.path).unwrap(),
+            std::fs::read(&gif_path).unwrap()
+        );
+    }
 }
@@ -0,0 +1,483 @@
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use image::{GenericImageView, ImageFormat};
+
+use crate::animated_encoder::{AnimationInput, AnimationJob, AnimationOutput};
+use crate::atomic_file::AtomicFile;
+use crate::create_thumbnail::{resize_decoded, save_static_thumbnail, Thumbnail};
+use crate::errors::ThumbnailError;
+use crate::ffmpeg::FfmpegConfig;
+use crate::get_thumbnail_dimensions::{calculate_dimensions, TargetDimension};
+use crate::if_exists::resolve_output_paths;
+use crate::is_same_path::is_same_path;
+use crate::options::ThumbnailOptions;
+
+/// File extensions which we treat as videos, and pass to `ffmpeg`
+/// rather than the `image` crate.
+const VIDEO_EXTENSIONS: &[&str] = &["avi", "m4v", "mkv", "mov", "mp4", "webm"];
+
+/// Brands of ISO media files (the same container as MP4) which hold
+/// still images rather than videos, e.g. HEIC photos and AVIF.
+const STILL_IMAGE_BRANDS: &[&[u8; 4]] = &[b"avif", b"heic", b"heix", b"mif1", b"msf1"];
+
+/// Returns True if a file looks like a video.
+///
+/// We look at the first few bytes of the file, like we do for images:
+/// an MP4/MOV, Matroska/WebM or AVI file is a video whatever it's called,
+/// and an image is never a video.  If we can't read the file, or we don't
+/// recognise its contents, we go by its extension.
+pub fn is_video(path: &Path) -> bool {
+    let mut header = Vec::with_capacity(12);
+    let _ = File::open(path).and_then(|f| f.take(12).read_to_end(&mut header));
+
+    if has_video_magic(&header) {
+        return true;
+    }
+
+    if image::guess_format(&header).is_ok() {
+        return false;
+    }
+
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some(ext) => VIDEO_EXTENSIONS.contains(&ext.to_lowercase().as_str()),
+        None => false,
+    }
+}
+
+/// Returns True if `header` is the start of a video container.
+fn has_video_magic(header: &[u8]) -> bool {
+    match header {
+        // MP4, MOV and M4V are ISO media files, which start with the
+        // length of an `ftyp` box, then the brand.
+        [_, _, _, _, b'f', b't', b'y', b'p', brand @ ..] => {
+            !STILL_IMAGE_BRANDS.iter().any(|b| brand.starts_with(*b))
+        }
+
+        // Matroska and WebM start with an EBML header.
+        [0x1A, 0x45, 0xDF, 0xA3, ..] => true,
+
+        // AVI is a RIFF file with the form type `AVI `.
+        [b'R', b'I', b'F', b'F', _, _, _, _, b'A', b'V', b'I', b' ', ..] => true,
+
+        _ => false,
+    }
+}
+
+/// Information about a video, as reported by `ffprobe`.
+#[derive(Debug, PartialEq)]
+pub struct VideoInfo {
+    /// Width of the video frames, after any rotation is applied
+    pub width: u32,
+
+    /// Height of the video frames, after any rotation is applied
+    pub height: u32,
+
+    /// Duration of the video, in seconds
+    pub duration: f64,
+}
+
+/// Use `ffprobe` to get the dimensions and duration of a video.
+pub fn probe_video(path: &Path, ffmpeg: &FfmpegConfig) -> Result<VideoInfo, ThumbnailError> {
+    let mut args = [
+        "-v",
+        "error",
+        "-select_streams",
+        "v:0",
+        "-show_entries",
+        "stream=width,height:stream_side_data=rotation:format=duration",
+        "-of",
+        "default=noprint_wrappers=1",
+    ]
+    .map(OsString::from)
+    .to_vec();
+    args.push(path.into());
+
+    let output = ffmpeg.run_ffprobe(path, &args)?;
+
+    // If ffprobe prints something which isn't UTF-8, it won't parse,
+    // and we show it in the error as best we can.
+    let stdout = String::from_utf8_lossy(&output);
+    parse_ffprobe_output(&stdout).ok_or_else(|| ThumbnailError::VideoProbeFailed {
+        path: path.to_path_buf(),
+        output: stdout.to_string(),
+    })
+}
+
+/// Parse the `key=value` lines printed by `ffprobe`.
+///
+/// If the video has a rotation of ±90 degrees, ffmpeg will rotate the
+/// frames when it decodes them, so we swap the width and height.
+fn parse_ffprobe_output(stdout: &str) -> Option<VideoInfo> {
+    let mut width = None;
+    let mut height = None;
+    let mut duration = None;
+    let mut rotation = 0;
+
+    for line in stdout.lines() {
+        match line.trim().split_once('=') {
+            Some(("width", value)) => width = value.parse::<u32>().ok(),
+            Some(("height", value)) => height = value.parse::<u32>().ok(),
+            Some(("duration", value)) => duration = value.parse::<f64>().ok(),
+            Some(("rotation", value)) => rotation = value.parse::<i32>().unwrap_or(0),
+            _ => (),
+        }
+    }
+
+    let (width, height) = match (width?, height?) {
+        (w, h) if rotation.rem_euclid(180) == 90 => (h, w),
+        (w, h) => (w, h),
+    };
+
+    Some(VideoInfo {
+        width,
+        height,
+        duration: duration?,
+    })
+}
+
+/// Choose the timestamp of the frame to use as the thumbnail.
+///
+/// If the caller asked for a particular timestamp we use that, otherwise
+/// we pick a frame 10% of the way through, which tends to skip title
+/// cards and fade-ins.
+///
+/// If the requested timestamp isn't inside the video, we fall back to
+/// the default, and return a warning saying so.
+fn choose_timestamp(duration: f64, frame_at: Option<f64>) -> (f64, Option<String>) {
+    let default = duration * 0.1;
+
+    match frame_at {
+        Some(t) if t >= 0.0 && t < duration => (t, None),
+        Some(t) => (
+            default,
+            Some(format!(
+                "--frame-at={} is outside the video, which is {} seconds long; \
+                 using the frame at {} seconds instead",
+                t, duration, default
+            )),
+        ),
+        None => (default, None),
+    }
+}
+
+/// Create a thumbnail for a video.
+///
+/// This uses `ffmpeg` to grab a single frame from the video, which we
+/// resize and save, the same as we would for a static image.
+///
+/// If `options.video_preview_seconds` is set, we also create a short
+/// clip alongside the still thumbnail, which starts at the same frame and can
+/// be played on a loop.  The clip is created by `options.animated_encoder`.
+///
+/// The still thumbnail is saved at `thumbnail_path` as `format` (normally
+/// a JPEG), and the clip is saved next to it, with the encoder's extension.
+///
+/// If there's already a file at either path, `options.if_exists`
+/// decides what we do with it.  If we rename them, the still thumbnail
+/// and the clip get the same new name.
+///
+/// The caller probes the video first, and passes in its `info`.
+///
+/// This function assumes that the original video file definitely exists.
+///
+pub fn create_video_thumbnail(
+    video_path: &Path,
+    info: &VideoInfo,
+    thumbnail_path: &Path,
+    format: ImageFormat,
+    target: TargetDimension,
+    options: &ThumbnailOptions,
+) -> Result<Thumbnail, ThumbnailError> {
+    let (width, height) = calculate_dimensions((info.width, info.height), target);
+    let (timestamp, timestamp_warning) = choose_timestamp(info.duration, options.video_frame_at);
+
+    let preview = options.video_preview_seconds.map(|seconds| {
+        let path = thumbnail_path.with_extension(options.animated_encoder.extension());
+        (seconds, path)
+    });
+
+    if let Some((_, preview_path)) = &preview {
+        if is_same_path(video_path, preview_path) {
+            return Err(ThumbnailError::SameInputOutputPath(preview_path.clone()));
+        }
+    }
+
+    let mut paths = vec![thumbnail_path.to_path_buf()];
+    paths.extend(preview.iter().map(|(_, path)| path.clone()));
+    let mut resolved = resolve_output_paths(&paths, options.if_exists)?.into_iter();
+
+    // If we're skipping the still thumbnail, we still know how big it
+    // would be, which is all we need for the preview clip.
+    let still_path = resolved.next().flatten();
+    let (width, height) = match &still_path {
+        Some(still_path) => {
+            let frame = extract_frame(video_path, timestamp, &options.ffmpeg)?;
+            let resized = resize_decoded(&frame, width, height);
+            save_static_thumbnail(&resized, format, still_path)?;
+            resized.dimensions()
+        }
+        None => (width, height),
+    };
+
+    let mut thumbnail = Thumbnail {
+        path: still_path
+            .clone()
+            .unwrap_or_else(|| thumbnail_path.to_path_buf()),
+        width,
+        height,
+        preview_path: None,
+        ffmpeg_args: None,
+        warnings: timestamp_warning.into_iter().collect(),
+        skipped: still_path.is_none(),
+    };
+
+    if let Some((seconds, preview_path)) = preview {
+        match resolved.next().flatten() {
+            Some(preview_path) => {
+                let file = AtomicFile::new(&preview_path);
+
+                let encoded = options.animated_encoder.encode(&AnimationJob {
+                    input: AnimationInput::Path(video_path),
+                    output: AnimationOutput::Path(file.temp_path()),
+                    width,
+                    height,
+                    start: Some(timestamp),
+                    duration: Some(seconds),
+                    background: options.background,
+                })?;
+
+                file.persist()
+                    .map_err(|e| ThumbnailError::io(&preview_path, e))?;
+
+                thumbnail.ffmpeg_args = encoded.ffmpeg_args;
+                thumbnail.skipped = false;
+                thumbnail.preview_path = Some(preview_path);
+            }
+            None => thumbnail.preview_path = Some(preview_path),
+        }
+    }
+
+    Ok(thumbnail)
+}
+
+/// Extract a single frame from a video, and decode it as an image.
+///
+/// We ask ffmpeg to write the frame as a PNG to stdout, so we don't
+/// need to create a temporary file.
+fn extract_frame(
+    video_path: &Path,
+    timestamp: f64,
+    ffmpeg: &FfmpegConfig,
+) -> Result<image::DynamicImage, ThumbnailError> {
+    let args: [OsString; 11] = [
+        "-ss".into(),
+        timestamp.to_string().into(),
+        "-i".into(),
+        video_path.into(),
+        "-frames:v".into(),
+        "1".into(),
+        "-f".into(),
+        "image2pipe".into(),
+        "-c:v".into(),
+        "png".into(),
+        "-".into(),
+    ];
+
+    let png_bytes = ffmpeg.run_ffmpeg(video_path, &args)?;
+
+    image::load_from_memory_with_format(&png_bytes, ImageFormat::Png)
+        .map_err(|e| ThumbnailError::image(video_path, e))
+}
+
+#[cfg(test)]
+mod test_create_video_thumbnail {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::if_exists::IfExists;
+    use crate::test_utils::{ffmpeg_is_installed, get_dimensions, get_video_length, test_dir};
+
+    #[test]
+    fn it_recognises_video_extensions() {
+        assert!(is_video(&PathBuf::from("cat.mp4")));
+        assert!(is_video(&PathBuf::from("cat.MOV")));
+        assert!(is_video(&PathBuf::from("path/to/cat.webm")));
+    }
+
+    #[test]
+    fn images_are_not_videos() {
+        assert!(!is_video(&PathBuf::from("src/tests/red.png")));
+        assert!(!is_video(&PathBuf::from("src/tests/animated_squares.gif")));
+        assert!(!is_video(&PathBuf::from("README")));
+    }
+
+    #[test]
+    fn it_recognises_a_video_by_its_contents() {
+        let dir = test_dir();
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let avi = dir.join("bars.dat");
+        std::fs::copy("src/tests/bars.avi", &avi).unwrap();
+        assert!(is_video(&avi));
+
+        let png = dir.join("red.mp4");
+        std::fs::copy("src/tests/red.png", &png).unwrap();
+        assert!(!is_video(&png));
+    }
+
+    #[test]
+    fn it_recognises_video_magic_bytes() {
+        assert!(has_video_magic(b"\0\0\0\x20ftypisom\0\0\x02\0"));
+        assert!(has_video_magic(b"\0\0\0\x14ftypqt  "));
+        assert!(has_video_magic(b"\x1A\x45\xDF\xA3\x9F\x42\x86\x81"));
+        assert!(has_video_magic(b"RIFF\x10\0\0\0AVI LIST"));
+
+        assert!(!has_video_magic(b"\0\0\0\x18ftypheic\0\0\0\0"));
+        assert!(!has_video_magic(b"RIFF\x10\0\0\0WEBPVP8 "));
+        assert!(!has_video_magic(b""));
+    }
+
+    #[test]
+    fn it_parses_ffprobe_output() {
+        let stdout = "width=1920\nheight=1080\nduration=12.500000\n";
+
+        assert_eq!(
+            parse_ffprobe_output(stdout),
+            Some(VideoInfo {
+                width: 1920,
+                height: 1080,
+                duration: 12.5
+            })
+        );
+    }
+
+    #[test]
+    fn it_swaps_dimensions_of_rotated_videos() {
+        let stdout = "width=1920\nheight=1080\nrotation=-90\nduration=3.0\n";
+
+        assert_eq!(
+            parse_ffprobe_output(stdout),
+            Some(VideoInfo {
+                width: 1080,
+                height: 1920,
+                duration: 3.0
+            })
+        );
+    }
+
+    #[test]
+    fn it_rejects_incomplete_ffprobe_output() {
+        assert_eq!(parse_ffprobe_output("width=1920\nheight=1080\n"), None);
+        assert_eq!(parse_ffprobe_output(""), None);
+    }
+
+    #[test]
+    fn it_defaults_to_ten_percent_through_the_video() {
+        assert_eq!(choose_timestamp(30.0, None), (3.0, None));
+    }
+
+    #[test]
+    fn it_uses_the_requested_timestamp() {
+        assert_eq!(choose_timestamp(30.0, Some(12.0)), (12.0, None));
+    }
+
+    #[test]
+    fn it_warns_about_a_timestamp_outside_the_video() {
+        assert_eq!(
+            choose_timestamp(30.0, Some(45.0)),
+            (
+                3.0,
+                Some(
+                    "--frame-at=45 is outside the video, which is 30 seconds long; \
+                     using the frame at 3 seconds instead"
+                        .to_string()
+                )
+            )
+        );
+
+        let (timestamp, warning) = choose_timestamp(30.0, Some(-1.0));
+        assert_eq!(timestamp, 3.0);
+        assert!(warning
+            .unwrap()
+            .starts_with("--frame-at=-1 is outside the video"));
+    }
+
+    #[test]
+    fn it_creates_a_thumbnail_and_a_preview_of_a_video() {
+        if !ffmpeg_is_installed() {
+            return;
+        }
+
+        // This video is 48×32, and has 10 frames at 10 fps.
+        let video_path = PathBuf::from("src/tests/bars.avi");
+        let options = ThumbnailOptions {
+            video_preview_seconds: Some(0.5),
+            ..Default::default()
+        };
+
+        let info = probe_video(&video_path, &options.ffmpeg).unwrap();
+        assert_eq!((info.width, info.height), (48, 32));
+        assert!((info.duration - 1.0).abs() < 0.05, "{:?}", info);
+
+        let out_dir = test_dir();
+        std::fs::create_dir_all(&out_dir).unwrap();
+
+        let thumbnail = create_video_thumbnail(
+            &video_path,
+            &info,
+            &out_dir.join("bars.jpg"),
+            ImageFormat::Jpeg,
+            TargetDimension::MaxWidth(24),
+            &options,
+        )
+        .unwrap();
+
+        assert_eq!(thumbnail.path, out_dir.join("bars.jpg"));
+        assert_eq!((thumbnail.width, thumbnail.height), (24, 16));
+        assert_eq!(get_dimensions(&thumbnail.path), (24, 16));
+
+        let preview_path = thumbnail.preview_path.unwrap();
+        assert_eq!(preview_path, out_dir.join("bars.mp4"));
+
+        let (frames, duration) = get_video_length(&preview_path);
+        assert_eq!(frames, 5);
+        assert!((duration - 0.5).abs() < 0.05, "duration = {}", duration);
+    }
+
+    #[test]
+    fn it_renames_the_still_and_the_preview_together() {
+        if !ffmpeg_is_installed() {
+            return;
+        }
+
+        let video_path = PathBuf::from("src/tests/bars.avi");
+        let options = ThumbnailOptions {
+            video_preview_seconds: Some(0.5),
+            if_exists: IfExists::Rename,
+            ..Default::default()
+        };
+        let info = probe_video(&video_path, &options.ffmpeg).unwrap();
+
+        // Only the preview clip is in the way, but we rename both, so
+        // they still have matching names.
+        let out_dir = test_dir();
+        std::fs::create_dir_all(&out_dir).unwrap();
+        std::fs::write(out_dir.join("bars.mp4"), "").unwrap();
+
+        let thumbnail = create_video_thumbnail(
+            &video_path,
+            &info,
+            &out_dir.join("bars.jpg"),
+            ImageFormat::Jpeg,
+            TargetDimension::MaxWidth(24),
+            &options,
+        )
+        .unwrap();
+
+        assert_eq!(thumbnail.path, out_dir.join("bars-1.jpg"));
+        assert_eq!(thumbnail.preview_path, Some(out_dir.join("bars-1.mp4")));
+    }
+}
@@ -0,0 +1,228 @@
+use std::fmt;
+use std::time::Duration;
+
+use image::{ImageDecoder, Limits};
+
+/// Limits on the images we're willing to decode.
+///
+/// A small file can claim to be an enormous image -- e.g. a PNG which
+/// decompresses to 100000×100000 pixels -- and decoding it would use up
+/// all the memory on the machine.  We check these limits using the image
+/// headers, before we decode any pixel data.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DecodeLimits {
+    /// The maximum number of pixels in an image
+    pub max_pixels: Option<u64>,
+
+    /// The maximum size of the original file, in bytes
+    pub max_input_bytes: Option<u64>,
+
+    /// The maximum amount of memory a decoded image can use, in bytes
+    pub max_alloc: Option<u64>,
+
+    /// The maximum number of frames in an animated GIF
+    pub max_frames: Option<u64>,
+
+    /// The maximum length of an animated GIF
+    pub max_animation_duration: Option<Duration>,
+}
+
+/// The default memory limit, which is the same as the `image` crate.
+pub const DEFAULT_MAX_ALLOC: u64 = 512 * 1024 * 1024;
+
+impl Default for DecodeLimits {
+    fn default() -> Self {
+        DecodeLimits {
+            max_pixels: None,
+            max_input_bytes: None,
+            max_alloc: Some(DEFAULT_MAX_ALLOC),
+            max_frames: None,
+            max_animation_duration: None,
+        }
+    }
+}
+
+/// Which limit an image exceeded, and by how much.
+#[derive(Debug, PartialEq)]
+pub enum LimitExceeded {
+    Pixels {
+        pixels: u64,
+        max: u64,
+    },
+    InputBytes {
+        bytes: u64,
+        max: u64,
+    },
+    Alloc {
+        bytes: u64,
+        max: u64,
+    },
+    Frames {
+        frames: u64,
+        max: u64,
+    },
+    AnimationDuration {
+        duration: Duration,
+        max: Duration,
+    },
+
+    /// The decoder refused the image, e.g. because one of its own
+    /// limits was exceeded
+    Decoder(String),
+}
+
+impl fmt::Display for LimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LimitExceeded::Pixels { pixels, max } => {
+                write!(f, "it has {} pixels, but the limit is {}", pixels, max)
+            }
+            LimitExceeded::InputBytes { bytes, max } => {
+                write!(f, "the file is {} bytes, but the limit is {}", bytes, max)
+            }
+            LimitExceeded::Alloc { bytes, max } => write!(
+                f,
+                "decoding it would use {} bytes of memory, but the limit is {}",
+                bytes, max
+            ),
+            LimitExceeded::Frames { frames, max } => {
+                write!(
+                    f,
+                    "it has at least {} frames, but the limit is {}",
+                    frames, max
+                )
+            }
+            LimitExceeded::AnimationDuration { duration, max } => write!(
+                f,
+                "it lasts at least {} seconds, but the limit is {}",
+                duration.as_secs_f64(),
+                max.as_secs_f64()
+            ),
+            LimitExceeded::Decoder(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl DecodeLimits {
+    /// Check the size of the original file.
+    pub fn check_input_bytes(&self, bytes: u64) -> Result<(), LimitExceeded> {
+        match self.max_input_bytes {
+            Some(max) if bytes > max => Err(LimitExceeded::InputBytes { bytes, max }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Check the dimensions and memory usage of an image, based on the
+    /// information in its headers.
+    pub fn check_decoder(&self, decoder: &impl ImageDecoder) -> Result<(), LimitExceeded> {
+        let (width, height) = decoder.dimensions();
+        let pixels = width as u64 * height as u64;
+
+        if let Some(max) = self.max_pixels {
+            if pixels > max {
+                return Err(LimitExceeded::Pixels { pixels, max });
+            }
+        }
+
+        if let Some(max) = self.max_alloc {
+            let bytes = decoder.total_bytes();
+            if bytes > max {
+                return Err(LimitExceeded::Alloc { bytes, max });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Check the number of frames and length of an animation.
+    pub fn check_animation(&self, frames: u64, duration: Duration) -> Result<(), LimitExceeded> {
+        if let Some(max) = self.max_frames {
+            if frames > max {
+                return Err(LimitExceeded::Frames { frames, max });
+            }
+        }
+
+        if let Some(max) = self.max_animation_duration {
+            if duration > max {
+                return Err(LimitExceeded::AnimationDuration { duration, max });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Whether we need to look at every frame of an animation to check
+    /// these limits.
+    pub fn limits_animation(&self) -> bool {
+        self.max_frames.is_some() || self.max_animation_duration.is_some()
+    }
+
+    /// The limits to pass to the decoders in the `image` crate.
+    ///
+    /// We check the memory limit ourselves first, so we can report how
+    /// much memory the image needs, but we also pass it to the decoder in
+    /// case it needs to allocate anything we didn't know about.
+    pub fn image_limits(&self) -> Limits {
+        let mut limits = Limits::no_limits();
+        limits.max_alloc = self.max_alloc;
+        limits
+    }
+}
+
+#[cfg(test)]
+mod test_decode_limits {
+    use super::*;
+
+    #[test]
+    fn there_are_no_limits_on_animations_by_default() {
+        let limits = DecodeLimits::default();
+
+        assert!(!limits.limits_animation());
+        assert!(limits
+            .check_animation(10_000, Duration::from_secs(3600))
+            .is_ok());
+    }
+
+    #[test]
+    fn it_rejects_a_file_which_is_too_big() {
+        let limits = DecodeLimits {
+            max_input_bytes: Some(1000),
+            ..Default::default()
+        };
+
+        assert!(limits.check_input_bytes(1000).is_ok());
+        assert!(matches!(
+            limits.check_input_bytes(1001),
+            Err(LimitExceeded::InputBytes {
+                bytes: 1001,
+                max: 1000
+            })
+        ));
+    }
+
+    #[test]
+    fn it_rejects_an_animation_which_is_too_long() {
+        let limits = DecodeLimits {
+            max_animation_duration: Some(Duration::from_secs(5)),
+            ..Default::default()
+        };
+
+        assert!(matches!(
+            limits.check_animation(100, Duration::from_millis(5500)),
+            Err(LimitExceeded::AnimationDuration { .. })
+        ));
+    }
+
+    #[test]
+    fn it_explains_which_limit_was_exceeded() {
+        let exceeded = LimitExceeded::Alloc {
+            bytes: 10_000_000_000,
+            max: DEFAULT_MAX_ALLOC,
+        };
+
+        assert_eq!(
+            exceeded.to_string(),
+            "decoding it would use 10000000000 bytes of memory, but the limit is 536870912"
+        );
+    }
+}
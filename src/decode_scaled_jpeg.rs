@@ -0,0 +1,197 @@
+use image::{DynamicImage, GrayImage, RgbImage};
+use jpeg_decoder::{Decoder, PixelFormat};
+
+use crate::decode_limits::{DecodeLimits, LimitExceeded};
+
+/// Decode a JPEG at a reduced size, if it's much bigger than the
+/// thumbnail we want to create.
+///
+/// JPEG decoders can skip most of the work of decoding an image by
+/// scaling it down to 1/2, 1/4 or 1/8 of the original size as part
+/// of the DCT.  We pick the smallest of those sizes which is still at
+/// least `width`×`height`, so we can finish with a high-quality resize.
+///
+/// This uses a different decoder to the `image` crate, so the pixels
+/// aren't identical to a full decode -- but the difference is well below
+/// what survives resizing to a thumbnail, which the tests check.
+///
+/// The dimensions are of the image as stored, before any EXIF orientation
+/// is applied.  This returns `Ok(None)` if we can't use the fast path, e.g.
+/// for CMYK images, and the caller should do a full decode instead.  It
+/// checks `limits.max_alloc` before decoding any pixel data.
+pub fn decode_scaled_jpeg(
+    bytes: &[u8],
+    width: u32,
+    height: u32,
+    limits: &DecodeLimits,
+) -> Result<Option<DynamicImage>, LimitExceeded> {
+    let mut decoder = Decoder::new(bytes);
+    if decoder.read_info().is_err() {
+        return Ok(None);
+    }
+    let Some(info) = decoder.info() else {
+        return Ok(None);
+    };
+
+    let (full_width, full_height) = (info.width as u32, info.height as u32);
+    let Some((scaled_width, scaled_height)) = choose_scale(full_width, full_height, width, height)
+    else {
+        return Ok(None);
+    };
+
+    // The decoder picks a scale which is big enough in *either* dimension,
+    // so we ask for exactly the size we've chosen, then check we got it.
+    let Ok((w, h)) = decoder.scale(scaled_width as u16, scaled_height as u16) else {
+        return Ok(None);
+    };
+    let (w, h) = (w as u32, h as u32);
+
+    if w < width || h < height {
+        return Ok(None);
+    }
+
+    if let Some(max) = limits.max_alloc {
+        let bytes = w as u64 * h as u64 * info.pixel_format.pixel_bytes() as u64;
+        if bytes > max {
+            return Err(LimitExceeded::Alloc { bytes, max });
+        }
+
+        decoder.set_max_decoding_buffer_size(usize::try_from(max).unwrap_or(usize::MAX));
+    }
+
+    let Ok(pixels) = decoder.decode() else {
+        return Ok(None);
+    };
+
+    Ok(match info.pixel_format {
+        PixelFormat::L8 => GrayImage::from_raw(w, h, pixels).map(DynamicImage::ImageLuma8),
+        PixelFormat::RGB24 => RgbImage::from_raw(w, h, pixels).map(DynamicImage::ImageRgb8),
+        PixelFormat::L16 | PixelFormat::CMYK32 => None,
+    })
+}
+
+/// Choose the smallest DCT scale (1/8, 1/4 or 1/2) which produces an
+/// image at least `width`×`height`, and return the scaled dimensions.
+///
+/// This returns `None` if the image can't be scaled down at all.
+fn choose_scale(full_width: u32, full_height: u32, width: u32, height: u32) -> Option<(u32, u32)> {
+    let scaled = |len: u32, eighths: u32| (len * eighths).div_ceil(8);
+
+    [1, 2, 4]
+        .into_iter()
+        .map(|eighths| (scaled(full_width, eighths), scaled(full_height, eighths)))
+        .find(|&(w, h)| w >= width && h >= height)
+}
+
+#[cfg(test)]
+mod test_decode_scaled_jpeg {
+    use std::fs;
+
+    use image::GenericImageView;
+
+    use super::*;
+
+    macro_rules! choose_scale_tests {
+        ($($name:ident: $value:expr,)*) => {
+        $(
+            #[test]
+            fn $name() {
+                let (full, target, expected) = $value;
+
+                assert_eq!(choose_scale(full.0, full.1, target.0, target.1), expected);
+            }
+        )*
+        }
+    }
+
+    choose_scale_tests! {
+        eighth_scale:       ((8000, 6000), (200, 150),   Some((1000, 750))),
+        quarter_scale:      ((8000, 6000), (1500, 1125), Some((2000, 1500))),
+        half_scale:         ((8000, 6000), (3000, 2250), Some((4000, 3000))),
+        no_scale:           ((8000, 6000), (5000, 3750), None),
+        rounds_up:          ((1001, 1001), (125, 125),   Some((126, 126))),
+        needs_both_axes:    ((1600, 800),  (100, 101),   Some((400, 200))),
+    }
+
+    #[test]
+    fn it_decodes_a_jpeg_at_a_smaller_scale() {
+        let bytes = fs::read("src/tests/Landscape_5.jpg").unwrap();
+
+        let img = decode_scaled_jpeg(&bytes, 300, 200, &DecodeLimits::default())
+            .unwrap()
+            .unwrap();
+
+        // The image is 1200×1800 as stored, so 1/4 scale is the smallest
+        // which is still at least 300×200.
+        assert_eq!(img.dimensions(), (300, 450));
+    }
+
+    #[test]
+    fn it_does_not_scale_if_the_thumbnail_is_nearly_full_size() {
+        let bytes = fs::read("src/tests/Landscape_5.jpg").unwrap();
+
+        assert!(
+            decode_scaled_jpeg(&bytes, 1000, 1500, &DecodeLimits::default())
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn it_returns_none_for_a_non_jpeg() {
+        let bytes = fs::read("src/tests/red.png").unwrap();
+
+        assert!(decode_scaled_jpeg(&bytes, 10, 10, &DecodeLimits::default())
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn it_checks_the_memory_limit_before_decoding() {
+        let bytes = fs::read("src/tests/Landscape_5.jpg").unwrap();
+        let limits = DecodeLimits {
+            max_alloc: Some(1000),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            decode_scaled_jpeg(&bytes, 300, 200, &limits),
+            Err(LimitExceeded::Alloc {
+                bytes: 300 * 450 * 3,
+                max: 1000
+            })
+        );
+    }
+
+    #[test]
+    fn the_thumbnail_is_nearly_the_same_as_a_full_decode() {
+        let bytes = fs::read("src/tests/Landscape_5.jpg").unwrap();
+
+        let scaled = decode_scaled_jpeg(&bytes, 300, 200, &DecodeLimits::default())
+            .unwrap()
+            .unwrap();
+        let full = image::load_from_memory(&bytes).unwrap();
+
+        // Resize both images to the same thumbnail, and compare every
+        // sample in the two thumbnails.
+        let resize = |img: &DynamicImage| {
+            img.resize_exact(200, 300, image::imageops::FilterType::Lanczos3)
+                .to_rgb8()
+        };
+        let (scaled, full) = (resize(&scaled), resize(&full));
+
+        let differences: Vec<u8> = scaled
+            .as_raw()
+            .iter()
+            .zip(full.as_raw())
+            .map(|(a, b)| a.abs_diff(*b))
+            .collect();
+        let mean = differences.iter().map(|&d| d as f64).sum::<f64>() / differences.len() as f64;
+        let max = *differences.iter().max().unwrap();
+
+        // A handful of samples on sharp edges differ noticeably, but on
+        // average the two thumbnails are almost identical.
+        assert!(mean < 2.0, "mean difference is {}", mean);
+        assert!(max <= 48, "max difference is {}", max);
+    }
+}
@@ -1,54 +1,408 @@
+use std::error::Error;
 use std::fmt;
 use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 use image::ImageError;
 
+use crate::decode_limits::LimitExceeded;
+
+/// Everything that can go wrong while creating a thumbnail.
+///
+/// Where a problem is caused by a particular file, the error includes its
+/// path.  Images we read from memory don't have a path, so it's empty.
+/// Each kind of error has its own exit code; see [`ThumbnailError::exit_code`].
 #[derive(Debug)]
 pub enum ThumbnailError {
-    MissingFileName,
-    ImageOpenError(ImageError),
-    ImageSaveError(ImageError),
-    CommandFailed(String),
-    Utf8Error(std::str::Utf8Error),
-    PathConversionError,
-    SameInputOutputPath,
-    IoError(std::io::Error),
+    /// The path doesn't end in a file name, e.g. `/` or `..`
+    MissingFileName(PathBuf),
+
+    /// The file isn't an image, or it's in a format we can't read
+    UnsupportedFormat { path: PathBuf, source: ImageError },
+
+    /// The file looks like an image, but we couldn't decode it
+    CorruptImage { path: PathBuf, source: ImageError },
+
+    /// The image is bigger than the `DecodeLimits` allow
+    LimitsExceeded { path: PathBuf, limit: LimitExceeded },
+
+    /// We couldn't encode or save a thumbnail.  The `path` is where we
+    /// were saving it, or the original image if it was only in memory.
+    ImageSaveError { path: PathBuf, source: ImageError },
+
+    /// A file or directory we need doesn't exist
+    NotFound { path: PathBuf, source: io::Error },
+
+    /// We aren't allowed to read or write a file
+    PermissionDenied { path: PathBuf, source: io::Error },
+
+    /// There isn't enough space on the disk to write a file
+    DiskFull { path: PathBuf, source: io::Error },
+
+    /// Something other than a directory, e.g. a file, is in the way of
+    /// the directory we want to save thumbnails in
+    OutDirNotADirectory { path: PathBuf },
+
+    /// We couldn't find `program` (ffmpeg or ffprobe) to work on the
+    /// file at `path`
+    FfmpegNotFound { path: PathBuf, program: PathBuf },
+
+    /// `program` (ffmpeg or ffprobe) ran for longer than `timeout` while
+    /// working on the file at `path`, so we stopped it
+    FfmpegTimedOut {
+        path: PathBuf,
+        program: PathBuf,
+        timeout: Duration,
+    },
+
+    /// ffmpeg failed while working on the file at `path`
+    EncodeFailed { path: PathBuf, message: String },
+
+    /// ffprobe couldn't tell us about the video at `path`
+    VideoProbeFailed { path: PathBuf, output: String },
+
+    /// The thumbnail would overwrite the original image at this path
+    SameInputOutputPath(PathBuf),
+
+    /// There's already a thumbnail at this path, and we were told not to
+    /// replace it (`IfExists::Error`).  Exits with code 9.
+    OutputExists(PathBuf),
+
+    /// Any other I/O error
+    IoError { path: PathBuf, source: io::Error },
+
+    /// The options don't make sense, e.g. two of them conflict.
+    /// Exits with code 2.
+    InvalidOptions(String),
+
+    /// The worker process failed with an error, and this exit code
+    WorkerFailed { message: String, exit_code: i32 },
+    /// The worker process creating a thumbnail of `path` died without
+    /// reporting an error, e.g. because it was killed by a signal, or we
+    /// couldn't start it.  Exits with code 13.
+    WorkerCrashed { path: PathBuf, message: String },
+
+    /// The worker process creating a thumbnail of `path` ran for longer
+    /// than `timeout`, so we stopped it.  Exits with code 13.
+    WorkerTimedOut { path: PathBuf, timeout: Duration },
+}
+
+/// Displays as e.g. ` on <path>` if we know the path, or nothing if we
+/// don't.
+struct MaybePath<'a>(&'static str, &'a Path);
+
+impl fmt::Display for MaybePath<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.0, self.1) {
+            (_, path) if path.as_os_str().is_empty() => Ok(()),
+            ("", path) => write!(f, " {}", path.display()),
+            (word, path) => write!(f, " {} {}", word, path.display()),
+        }
+    }
 }
 
 impl fmt::Display for ThumbnailError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            ThumbnailError::MissingFileName => write!(f, "Image path is missing a file name"),
-            ThumbnailError::ImageOpenError(e) => write!(f, "Failed to open image: {}", e),
-            ThumbnailError::ImageSaveError(e) => write!(f, "Failed to save thumbnail: {}", e),
-            ThumbnailError::CommandFailed(msg) => write!(f, "ffmpeg command failed: {}", msg),
-            ThumbnailError::Utf8Error(e) => write!(f, "Failed to decode ffmpeg output: {}", e),
-            ThumbnailError::PathConversionError => write!(f, "Failed to convert path to string"),
-            ThumbnailError::SameInputOutputPath => write!(
+            ThumbnailError::MissingFileName(path) => {
+                write!(f, "Image path {} is missing a file name", path.display())
+            }
+            ThumbnailError::UnsupportedFormat { path, source } => {
+                write!(f, "Failed to open image{}: {}", MaybePath("", path), source)
+            }
+            ThumbnailError::CorruptImage { path, source } => {
+                write!(
+                    f,
+                    "Failed to decode image{}: {}",
+                    MaybePath("", path),
+                    source
+                )
+            }
+            ThumbnailError::LimitsExceeded { path, limit } => write!(
+                f,
+                "Image{} is too big to thumbnail: {}",
+                MaybePath("", path),
+                limit
+            ),
+            ThumbnailError::ImageSaveError { path, source } => {
+                write!(
+                    f,
+                    "Failed to save thumbnail{}: {}",
+                    MaybePath("", path),
+                    source
+                )
+            }
+            ThumbnailError::NotFound { path, source } => {
+                write!(f, "Unable to find{}: {}", MaybePath("", path), source)
+            }
+            ThumbnailError::PermissionDenied { path, source } => {
+                write!(f, "Permission denied{}: {}", MaybePath("for", path), source)
+            }
+            ThumbnailError::DiskFull { path, source } => write!(
+                f,
+                "Not enough disk space to write{}: {}",
+                MaybePath("", path),
+                source
+            ),
+            ThumbnailError::OutDirNotADirectory { path } => write!(
+                f,
+                "Cannot save thumbnails in {}: it is not a directory",
+                path.display()
+            ),
+            ThumbnailError::FfmpegNotFound { path, program } => write!(
+                f,
+                "Unable to find {}{}; is ffmpeg installed?",
+                program.display(),
+                MaybePath("to read", path)
+            ),
+            ThumbnailError::FfmpegTimedOut {
+                path,
+                program,
+                timeout,
+            } => write!(
+                f,
+                "{} was stopped after running for {} seconds{}",
+                program.display(),
+                timeout.as_secs_f64(),
+                MaybePath("on", path)
+            ),
+            ThumbnailError::EncodeFailed { path, message } => {
+                write!(
+                    f,
+                    "ffmpeg command failed{}: {}",
+                    MaybePath("on", path),
+                    message
+                )
+            }
+            ThumbnailError::VideoProbeFailed { path, output } => write!(
+                f,
+                "Unable to read video information{} from ffprobe: {}",
+                MaybePath("about", path),
+                output
+            ),
+            ThumbnailError::SameInputOutputPath(path) => write!(
+                f,
+                "Cannot write thumbnail to {}: it is the original image",
+                path.display()
+            ),
+            ThumbnailError::OutputExists(path) => write!(
                 f,
-                "Cannot write thumbnail to the same path as the original image"
+                "Cannot write thumbnail to {}: the file already exists",
+                path.display()
+            ),
+            ThumbnailError::IoError { path, source } => {
+                write!(f, "I/O error{}: {}", MaybePath("on", path), source)
+            }
+            ThumbnailError::InvalidOptions(msg) => {
+                write!(f, "Failed to create thumbnail: {}", msg)
+            }
+
+            // The worker process has already formatted its error message.
+            ThumbnailError::WorkerFailed { message, .. } => write!(f, "{}", message),
+            ThumbnailError::WorkerCrashed { path, message } => write!(
+                f,
+                "The worker process crashed{}: {}",
+                MaybePath("on", path),
+                message
+            ),
+            ThumbnailError::WorkerTimedOut { path, timeout } => write!(
+                f,
+                "The worker process was stopped after running for {} seconds{}",
+                timeout.as_secs_f64(),
+                MaybePath("on", path)
             ),
-            ThumbnailError::IoError(e) => write!(f, "I/O error: {}", e),
         }
     }
 }
 
-impl std::error::Error for ThumbnailError {}
-
-impl From<ImageError> for ThumbnailError {
-    fn from(err: ImageError) -> Self {
-        ThumbnailError::ImageOpenError(err)
+impl Error for ThumbnailError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ThumbnailError::UnsupportedFormat { source, .. }
+            | ThumbnailError::CorruptImage { source, .. }
+            | ThumbnailError::ImageSaveError { source, .. } => Some(source),
+            ThumbnailError::NotFound { source, .. }
+            | ThumbnailError::PermissionDenied { source, .. }
+            | ThumbnailError::DiskFull { source, .. }
+            | ThumbnailError::IoError { source, .. } => Some(source),
+            _ => None,
+        }
     }
 }
 
-impl From<std::str::Utf8Error> for ThumbnailError {
-    fn from(err: std::str::Utf8Error) -> Self {
-        ThumbnailError::Utf8Error(err)
+impl ThumbnailError {
+    /// Sort an I/O error that happened while we were using `path` into
+    /// the right kind of error.
+    pub fn io(path: &Path, source: io::Error) -> ThumbnailError {
+        let path = path.to_path_buf();
+
+        match source.kind() {
+            io::ErrorKind::NotFound => ThumbnailError::NotFound { path, source },
+            io::ErrorKind::PermissionDenied => ThumbnailError::PermissionDenied { path, source },
+            io::ErrorKind::StorageFull => ThumbnailError::DiskFull { path, source },
+            _ => ThumbnailError::IoError { path, source },
+        }
+    }
+
+    /// The image at `path` exceeds one of the `DecodeLimits`.
+    pub fn limit(path: &Path, limit: LimitExceeded) -> ThumbnailError {
+        ThumbnailError::LimitsExceeded {
+            path: path.to_path_buf(),
+            limit,
+        }
+    }
+
+    /// Sort an error from the `image` crate while reading the image at
+    /// `path` into the right kind of error.
+    pub fn image(path: &Path, source: ImageError) -> ThumbnailError {
+        let path = path.to_path_buf();
+
+        match source {
+            ImageError::IoError(e) => ThumbnailError::io(&path, e),
+            ImageError::Unsupported(_) => ThumbnailError::UnsupportedFormat { path, source },
+            ImageError::Limits(e) => ThumbnailError::LimitsExceeded {
+                path,
+                limit: LimitExceeded::Decoder(e.to_string()),
+            },
+            _ => ThumbnailError::CorruptImage { path, source },
+        }
+    }
+
+    /// The exit code for this error, so scripts can tell different
+    /// failures apart:
+    ///
+    /// | Code | Meaning                                                 |
+    /// |------|---------------------------------------------------------|
+    /// | 1    | Any other error                                         |
+    /// | 2    | The options are invalid                                 |
+    /// | 3    | A file or directory doesn't exist                       |
+    /// | 4    | Permission denied                                       |
+    /// | 5    | The file isn't an image or video we can read            |
+    /// | 6    | The image or video is corrupt                           |
+    /// | 7    | The image is bigger than the decode limits              |
+    /// | 8    | The output directory isn't a directory                  |
+    /// | 9    | The thumbnail already exists, with `IfExists::Error`    |
+    /// | 10   | Encoding or saving the thumbnail failed                 |
+    /// | 11   | ffmpeg or ffprobe isn't installed                       |
+    /// | 12   | The disk is full                                        |
+    /// | 13   | The worker process crashed or timed out                 |
+    ///
+    /// If the worker process fails, we use its exit code.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            ThumbnailError::MissingFileName(_)
+            | ThumbnailError::SameInputOutputPath(_)
+            | ThumbnailError::InvalidOptions(_) => 2,
+            ThumbnailError::NotFound { .. } => 3,
+            ThumbnailError::PermissionDenied { .. } => 4,
+            ThumbnailError::UnsupportedFormat { .. } => 5,
+            ThumbnailError::CorruptImage { .. } | ThumbnailError::VideoProbeFailed { .. } => 6,
+            ThumbnailError::LimitsExceeded { .. } => 7,
+            ThumbnailError::OutDirNotADirectory { .. } => 8,
+            ThumbnailError::OutputExists(_) => 9,
+            ThumbnailError::ImageSaveError { .. }
+            | ThumbnailError::EncodeFailed { .. }
+            | ThumbnailError::FfmpegTimedOut { .. } => 10,
+            ThumbnailError::FfmpegNotFound { .. } => 11,
+            ThumbnailError::DiskFull { .. } => 12,
+            ThumbnailError::WorkerCrashed { .. } | ThumbnailError::WorkerTimedOut { .. } => 13,
+            ThumbnailError::WorkerFailed { exit_code, .. } => *exit_code,
+            ThumbnailError::IoError { .. } => 1,
+        }
     }
 }
 
-impl From<io::Error> for ThumbnailError {
-    fn from(err: io::Error) -> Self {
-        ThumbnailError::IoError(err)
+#[cfg(test)]
+mod test_errors {
+    use image::error::{ImageFormatHint, UnsupportedError};
+
+    use super::*;
+
+    #[test]
+    fn it_sorts_io_errors_by_kind() {
+        let path = Path::new("cat.jpg");
+
+        let err = ThumbnailError::io(path, io::Error::from(io::ErrorKind::NotFound));
+        assert!(
+            matches!(&err, ThumbnailError::NotFound { path, .. } if path == Path::new("cat.jpg"))
+        );
+        assert_eq!(err.exit_code(), 3);
+
+        let err = ThumbnailError::io(path, io::Error::from(io::ErrorKind::PermissionDenied));
+        assert_eq!(err.exit_code(), 4);
+
+        let err = ThumbnailError::io(path, io::Error::from(io::ErrorKind::StorageFull));
+        assert_eq!(err.exit_code(), 12);
+
+        let err = ThumbnailError::io(path, io::Error::from(io::ErrorKind::Interrupted));
+        assert_eq!(err.exit_code(), 1);
+    }
+
+    #[test]
+    fn it_sorts_image_errors_by_kind() {
+        let unsupported = ImageError::Unsupported(UnsupportedError::from(ImageFormatHint::Unknown));
+        let err = ThumbnailError::image(Path::new("cat.txt"), unsupported);
+
+        assert!(matches!(err, ThumbnailError::UnsupportedFormat { .. }));
+        assert_eq!(err.exit_code(), 5);
+        assert_eq!(
+            err.to_string(),
+            "Failed to open image cat.txt: The image format could not be determined"
+        );
+    }
+
+    #[test]
+    fn ffmpeg_errors_name_the_file() {
+        let err = ThumbnailError::FfmpegNotFound {
+            path: PathBuf::from("cat.gif"),
+            program: PathBuf::from("/usr/bin/ffmpeg"),
+        };
+        assert_eq!(
+            err.to_string(),
+            "Unable to find /usr/bin/ffmpeg to read cat.gif; is ffmpeg installed?"
+        );
+        assert_eq!(err.exit_code(), 11);
+
+        let err = ThumbnailError::FfmpegTimedOut {
+            path: PathBuf::from("cat.gif"),
+            program: PathBuf::from("/usr/bin/ffprobe"),
+            timeout: Duration::from_secs(30),
+        };
+        assert_eq!(
+            err.to_string(),
+            "/usr/bin/ffprobe was stopped after running for 30 seconds on cat.gif"
+        );
+        assert_eq!(err.exit_code(), 10);
+    }
+
+    #[test]
+    fn worker_errors_name_the_file() {
+        let err = ThumbnailError::WorkerCrashed {
+            path: PathBuf::from("bomb.png"),
+            message: "signal: 9 (SIGKILL)".to_string(),
+        };
+        assert_eq!(
+            err.to_string(),
+            "The worker process crashed on bomb.png: signal: 9 (SIGKILL)"
+        );
+        assert_eq!(err.exit_code(), 13);
+
+        let err = ThumbnailError::WorkerTimedOut {
+            path: PathBuf::from("bomb.png"),
+            timeout: Duration::from_secs(60),
+        };
+        assert_eq!(
+            err.to_string(),
+            "The worker process was stopped after running for 60 seconds on bomb.png"
+        );
+        assert_eq!(err.exit_code(), 13);
+    }
+
+    #[test]
+    fn it_exposes_the_underlying_error() {
+        let err = ThumbnailError::io(Path::new("cat.jpg"), io::Error::other("disk on fire"));
+
+        assert_eq!(err.source().unwrap().to_string(), "disk on fire");
     }
 }
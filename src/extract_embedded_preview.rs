@@ -0,0 +1,84 @@
+use std::io::Cursor;
+
+use exif::{In, Reader, Tag};
+use image::{DynamicImage, ImageFormat, ImageReader};
+
+use crate::decode_limits::DecodeLimits;
+
+/// Extract the preview image embedded in the EXIF metadata of an image,
+/// if there is one.
+///
+/// Many cameras store a small JPEG preview alongside the full image,
+/// in the second IFD of the EXIF data.  Decoding it is much cheaper than
+/// decoding the full image.
+///
+/// The preview is returned as stored, without any EXIF orientation applied.
+/// This returns `None` if there's no preview, we can't decode it, or it
+/// exceeds `limits` -- the preview is just as untrusted as the main image.
+pub fn extract_embedded_preview(bytes: &[u8], limits: &DecodeLimits) -> Option<DynamicImage> {
+    let exif = Reader::new()
+        .read_from_container(&mut Cursor::new(bytes))
+        .ok()?;
+
+    let offset = exif
+        .get_field(Tag::JPEGInterchangeFormat, In::THUMBNAIL)?
+        .value
+        .get_uint(0)? as usize;
+    let length = exif
+        .get_field(Tag::JPEGInterchangeFormatLength, In::THUMBNAIL)?
+        .value
+        .get_uint(0)? as usize;
+
+    // The offset is relative to the start of the EXIF data, not the file.
+    let preview = exif.buf().get(offset..offset.checked_add(length)?)?;
+
+    let mut reader = ImageReader::with_format(Cursor::new(preview), ImageFormat::Jpeg);
+    reader.limits(limits.image_limits());
+
+    let decoder = reader.into_decoder().ok()?;
+    limits.check_decoder(&decoder).ok()?;
+    DynamicImage::from_decoder(decoder).ok()
+}
+
+#[cfg(test)]
+mod test_extract_embedded_preview {
+    use std::fs;
+
+    use image::GenericImageView;
+
+    use super::*;
+
+    #[test]
+    fn it_extracts_an_embedded_preview() {
+        let bytes = fs::read("src/tests/embedded_preview.jpg").unwrap();
+
+        let preview = extract_embedded_preview(&bytes, &DecodeLimits::default()).unwrap();
+
+        assert_eq!(preview.dimensions(), (160, 120));
+    }
+
+    #[test]
+    fn it_returns_none_if_there_is_no_preview() {
+        let bytes = fs::read("src/tests/noise.jpg").unwrap();
+
+        assert!(extract_embedded_preview(&bytes, &DecodeLimits::default()).is_none());
+    }
+
+    #[test]
+    fn it_returns_none_for_an_image_without_exif() {
+        let bytes = fs::read("src/tests/red.png").unwrap();
+
+        assert!(extract_embedded_preview(&bytes, &DecodeLimits::default()).is_none());
+    }
+
+    #[test]
+    fn it_returns_none_if_the_preview_exceeds_the_limits() {
+        let bytes = fs::read("src/tests/embedded_preview.jpg").unwrap();
+        let limits = DecodeLimits {
+            max_pixels: Some(1000),
+            ..Default::default()
+        };
+
+        assert!(extract_embedded_preview(&bytes, &limits).is_none());
+    }
+}
@@ -0,0 +1,245 @@
+use std::ffi::OsString;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::Duration;
+
+use crate::errors::ThumbnailError;
+use crate::run_with_timeout::run_with_timeout;
+
+/// Where to find `ffmpeg` and `ffprobe`, and how long to let them run.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FfmpegConfig {
+    /// Path to the `ffmpeg` binary.  If this is a bare name like
+    /// `ffmpeg`, we look for it in the PATH.
+    pub ffmpeg: PathBuf,
+
+    /// Path to the `ffprobe` binary.
+    pub ffprobe: PathBuf,
+
+    /// How long to wait for either command before we kill it.
+    pub timeout: Duration,
+}
+
+impl Default for FfmpegConfig {
+    fn default() -> Self {
+        FfmpegConfig {
+            ffmpeg: PathBuf::from("ffmpeg"),
+            ffprobe: PathBuf::from("ffprobe"),
+            timeout: Duration::from_secs(300),
+        }
+    }
+}
+
+impl FfmpegConfig {
+    /// Run `ffmpeg` on the file at `path` with the given arguments, and
+    /// return its stdout.  We only use `path` in errors; it should
+    /// be empty if the input comes from memory.
+    ///
+    /// We always pass `-nostdin`, so ffmpeg never stops to wait for
+    /// keyboard input, and `-y`, so it overwrites an existing output
+    /// rather than asking for confirmation.  Deciding whether it's safe
+    /// to write to the output path is our job, not ffmpeg's.
+    ///
+    /// The arguments are `OsString`s, so we can pass paths which aren't
+    /// valid UTF-8 straight through to ffmpeg.
+    pub fn run_ffmpeg(&self, path: &Path, args: &[OsString]) -> Result<Vec<u8>, ThumbnailError> {
+        self.run_ffmpeg_with_input(path, args, None)
+    }
+
+    /// Run `ffmpeg` with the given arguments and `input` on stdin, and
+    /// return its stdout.
+    ///
+    /// This is how we stream data through ffmpeg without writing it to
+    /// disk, with `pipe:0` as the input and `pipe:1` as the output.
+    /// The `-nostdin` flag only stops ffmpeg reading keyboard commands;
+    /// it can still read input from `pipe:0`.
+    pub fn run_ffmpeg_with_input(
+        &self,
+        path: &Path,
+        args: &[OsString],
+        input: Option<&[u8]>,
+    ) -> Result<Vec<u8>, ThumbnailError> {
+        let mut cmd = Command::new(&self.ffmpeg);
+        cmd.args(["-nostdin", "-y", "-hide_banner"]).args(args);
+
+        run_ffmpeg_command(cmd, input, path, &self.ffmpeg, self.timeout)
+    }
+
+    /// Run `ffprobe` on the file at `path` with the given arguments, and
+    /// return its stdout.
+    pub fn run_ffprobe(&self, path: &Path, args: &[OsString]) -> Result<Vec<u8>, ThumbnailError> {
+        let mut cmd = Command::new(&self.ffprobe);
+        cmd.args(args);
+
+        run_ffmpeg_command(cmd, None, path, &self.ffprobe, self.timeout)
+    }
+}
+
+/// Run ffmpeg or ffprobe on the file at `path`, and turn anything that
+/// goes wrong into a `ThumbnailError`.
+fn run_ffmpeg_command(
+    cmd: Command,
+    input: Option<&[u8]>,
+    path: &Path,
+    program: &Path,
+    timeout: Duration,
+) -> Result<Vec<u8>, ThumbnailError> {
+    let output = match run_with_timeout(cmd, input, timeout) {
+        Ok(Some(output)) => output,
+        Ok(None) => {
+            return Err(ThumbnailError::FfmpegTimedOut {
+                path: path.to_path_buf(),
+                program: program.to_path_buf(),
+                timeout,
+            })
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            return Err(ThumbnailError::FfmpegNotFound {
+                path: path.to_path_buf(),
+                program: program.to_path_buf(),
+            })
+        }
+        Err(e) => return Err(ThumbnailError::io(program, e)),
+    };
+
+    if output.status.success() {
+        Ok(output.stdout)
+    } else {
+        Err(ThumbnailError::EncodeFailed {
+            path: path.to_path_buf(),
+            message: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        })
+    }
+}
+
+#[cfg(all(test, unix))]
+mod test_ffmpeg {
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+
+    use super::*;
+    use crate::test_utils::test_dir;
+
+    /// Create a shell script that we can use in place of ffmpeg.
+    fn fake_ffmpeg(script: &str) -> PathBuf {
+        let dir = test_dir();
+        fs::create_dir_all(&dir).unwrap();
+
+        let path = dir.join("ffmpeg");
+        fs::write(&path, format!("#!/bin/sh\n{}\n", script)).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+
+        path
+    }
+
+    #[test]
+    fn it_errors_if_ffmpeg_does_not_exist() {
+        let config = FfmpegConfig {
+            ffmpeg: PathBuf::from("/does/not/exist/ffmpeg"),
+            ..Default::default()
+        };
+
+        let result = config.run_ffmpeg(Path::new("cat.gif"), &[]);
+
+        match result {
+            Err(ThumbnailError::FfmpegNotFound { path, program }) => {
+                assert_eq!(path, PathBuf::from("cat.gif"));
+                assert_eq!(program, PathBuf::from("/does/not/exist/ffmpeg"));
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn it_always_passes_nostdin_and_an_overwrite_policy() {
+        let config = FfmpegConfig {
+            ffmpeg: fake_ffmpeg("echo \"$@\""),
+            ..Default::default()
+        };
+
+        let stdout = config.run_ffmpeg(Path::new("cat.gif"), &["-i".into(), "cat.gif".into()]);
+
+        assert_eq!(stdout.unwrap(), b"-nostdin -y -hide_banner -i cat.gif\n");
+    }
+
+    #[test]
+    fn it_kills_ffmpeg_if_it_takes_too_long() {
+        let config = FfmpegConfig {
+            ffmpeg: fake_ffmpeg("sleep 10"),
+            timeout: Duration::from_millis(100),
+            ..Default::default()
+        };
+
+        let result = config.run_ffmpeg(Path::new("cat.gif"), &[]);
+
+        assert!(matches!(
+            result,
+            Err(ThumbnailError::FfmpegTimedOut { path, program, .. })
+                if path == Path::new("cat.gif") && program == config.ffmpeg
+        ));
+    }
+
+    #[test]
+    fn it_says_if_it_was_ffprobe_which_took_too_long() {
+        let config = FfmpegConfig {
+            ffprobe: fake_ffmpeg("sleep 10"),
+            timeout: Duration::from_millis(100),
+            ..Default::default()
+        };
+
+        let result = config.run_ffprobe(Path::new("cat.mp4"), &[]);
+
+        assert!(matches!(
+            result,
+            Err(ThumbnailError::FfmpegTimedOut { program, .. }) if program == config.ffprobe
+        ));
+    }
+
+    #[test]
+    fn it_returns_stderr_if_ffmpeg_fails() {
+        let config = FfmpegConfig {
+            ffmpeg: fake_ffmpeg("echo 'width not divisible by 2' >&2; exit 1"),
+            ..Default::default()
+        };
+
+        let result = config.run_ffmpeg(Path::new("cat.gif"), &[]);
+
+        match result {
+            Err(ThumbnailError::EncodeFailed { path, message }) => {
+                assert_eq!(path, PathBuf::from("cat.gif"));
+                assert_eq!(message, "width not divisible by 2");
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn it_passes_input_to_ffmpeg() {
+        let config = FfmpegConfig {
+            ffmpeg: fake_ffmpeg("cat"),
+            ..Default::default()
+        };
+
+        let stdout = config.run_ffmpeg_with_input(Path::new(""), &[], Some(b"GIF89a"));
+
+        assert_eq!(stdout.unwrap(), b"GIF89a");
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn it_passes_arguments_which_arent_utf8() {
+        use std::os::unix::ffi::OsStringExt;
+
+        let config = FfmpegConfig {
+            ffmpeg: fake_ffmpeg("echo \"$@\""),
+            ..Default::default()
+        };
+
+        let path = PathBuf::from(OsString::from_vec(b"c\xe4t.gif".to_vec()));
+
+        let stdout = config.run_ffmpeg(&path, &["-i".into(), path.clone().into()]);
+
+        assert_eq!(stdout.unwrap(), b"-nostdin -y -hide_banner -i c\xe4t.gif\n");
+    }
+}
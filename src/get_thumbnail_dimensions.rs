@@ -1,18 +1,31 @@
-use std::path::PathBuf;
+use std::fmt;
 
-use image::GenericImageView;
-
-use crate::errors::ThumbnailError;
+use crate::source_image::SourceImage;
 
 /// Represents the target dimensions of the thumbnail.
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum TargetDimension {
     BoundingBox(u32, u32),
     MaxWidth(u32),
     MaxHeight(u32),
 }
 
-/// Given the path to the original image and the target width/height,
-/// calculate the dimensions of the new image.
+/// Formats the target as a short string, e.g. `w100`, `h250` or `100x250`.
+impl fmt::Display for TargetDimension {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TargetDimension::MaxWidth(w) => write!(f, "w{}", w),
+            TargetDimension::MaxHeight(h) => write!(f, "h{}", h),
+            TargetDimension::BoundingBox(w, h) => write!(f, "{}x{}", w, h),
+        }
+    }
+}
+
+/// Given the original image and the target width/height, calculate
+/// the dimensions of the new image.
+///
+/// This uses the dimensions read from the image headers, so it doesn't
+/// need to decode any pixel data.
 ///
 /// If the image is smaller than the target dimensions, it will be
 /// left as-is.
@@ -20,18 +33,13 @@ pub enum TargetDimension {
 /// TODO: Are there any scenarios in which this division could round
 /// one dimension of an image to zero, if it was very tall or very long?
 ///
-pub fn get_thumbnail_dimensions(
-    path: &PathBuf,
-    target: TargetDimension,
-) -> Result<(u32, u32), ThumbnailError> {
-    let img = image::open(path)?;
-
-    Ok(calculate_dimensions(img.dimensions(), target))
+pub fn get_thumbnail_dimensions(source: &SourceImage, target: TargetDimension) -> (u32, u32) {
+    calculate_dimensions(source.dimensions, target)
 }
 
-// Calculate the dimensions of the new image, given the original dimensions
-// and target dimensions.
-fn calculate_dimensions(dimensions: (u32, u32), target: TargetDimension) -> (u32, u32) {
+/// Calculate the dimensions of the new image, given the original dimensions
+/// and target dimensions.
+pub fn calculate_dimensions(dimensions: (u32, u32), target: TargetDimension) -> (u32, u32) {
     let (img_w, img_h) = dimensions;
 
     match target {
@@ -65,6 +73,8 @@ mod test_get_thumbnail_dimensions {
     use std::path::PathBuf;
 
     use super::*;
+    use crate::decode_limits::DecodeLimits;
+    use crate::errors::ThumbnailError;
 
     macro_rules! get_thumb_dimensions_tests {
         ($($name:ident: $value:expr,)*) => {
@@ -117,22 +127,30 @@ mod test_get_thumbnail_dimensions {
     }
 
     #[test]
-    fn errors_if_image_does_not_exist() {
-        let p = PathBuf::from("src/tests/doesnotexist.png");
+    fn uses_the_dimensions_of_the_source_image() {
+        let source = SourceImage::open(
+            &PathBuf::from("src/tests/red.png"),
+            &DecodeLimits::default(),
+        )
+        .unwrap();
 
         let target = TargetDimension::MaxWidth(50);
 
-        let dimensions = get_thumbnail_dimensions(&p, target);
-        assert!(dimensions.is_err());
+        assert_eq!(get_thumbnail_dimensions(&source, target), (50, 100));
+    }
+
+    #[test]
+    fn errors_if_image_does_not_exist() {
+        let p = PathBuf::from("src/tests/doesnotexist.png");
+
+        let result = SourceImage::open(&p, &DecodeLimits::default());
+        assert!(matches!(result, Err(ThumbnailError::NotFound { .. })));
     }
 
     #[test]
     fn errors_if_cannot_read_image() {
         let p = PathBuf::from("README.md");
 
-        let target = TargetDimension::MaxWidth(50);
-
-        let dimensions = get_thumbnail_dimensions(&p, target);
-        assert!(dimensions.is_err());
+        assert!(SourceImage::open(&p, &DecodeLimits::default()).is_err());
     }
 }
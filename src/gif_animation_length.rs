@@ -0,0 +1,123 @@
+use std::io::Read;
+use std::path::Path;
+use std::time::Duration;
+
+use image::error::{DecodingError, ImageFormatHint};
+use image::{ImageError, ImageFormat};
+
+use crate::decode_limits::DecodeLimits;
+use crate::errors::ThumbnailError;
+
+/// Returns the number of frames in the GIF at `path`, and how long it
+/// takes to play.
+///
+/// This only reads the metadata for each frame, and skips over the
+/// compressed pixel data, so it's much cheaper than decoding the GIF.
+///
+/// We check the animation `limits` after every frame, so a GIF with
+/// millions of frames fails as soon as it passes the limit, rather than
+/// after we've counted them all.
+pub fn gif_animation_length<R: Read>(
+    reader: R,
+    path: &Path,
+    limits: &DecodeLimits,
+) -> Result<(u64, Duration), ThumbnailError> {
+    let to_thumbnail_error = |e: gif::DecodingError| {
+        ThumbnailError::image(
+            path,
+            ImageError::Decoding(DecodingError::new(
+                ImageFormatHint::Exact(ImageFormat::Gif),
+                e,
+            )),
+        )
+    };
+
+    let mut options = gif::DecodeOptions::new();
+    options.skip_frame_decoding(true);
+    let mut decoder = options.read_info(reader).map_err(to_thumbnail_error)?;
+
+    let mut frames = 0;
+    let mut duration = Duration::ZERO;
+
+    // GIF frame delays are measured in hundredths of a second.
+    while let Some(frame) = decoder.next_frame_info().map_err(to_thumbnail_error)? {
+        frames += 1;
+        duration += Duration::from_millis(frame.delay as u64 * 10);
+
+        limits
+            .check_animation(frames, duration)
+            .map_err(|limit| ThumbnailError::limit(path, limit))?;
+    }
+
+    Ok((frames, duration))
+}
+
+#[cfg(test)]
+mod test_gif_animation_length {
+    use std::fs::File;
+
+    use image::codecs::gif::GifDecoder;
+    use image::AnimationDecoder;
+
+    use super::*;
+    use crate::decode_limits::LimitExceeded;
+
+    #[test]
+    fn it_matches_the_full_decoder() {
+        for name in [
+            "animated_squares.gif",
+            "transparent_squares.gif",
+            "static.gif",
+        ] {
+            let path = format!("src/tests/{name}");
+
+            let (frames, duration) = gif_animation_length(
+                File::open(&path).unwrap(),
+                Path::new(&path),
+                &DecodeLimits::default(),
+            )
+            .unwrap();
+
+            let decoded = GifDecoder::new(std::io::BufReader::new(File::open(&path).unwrap()))
+                .unwrap()
+                .into_frames()
+                .collect_frames()
+                .unwrap();
+            let expected_duration: Duration =
+                decoded.iter().map(|f| Duration::from(f.delay())).sum();
+
+            assert_eq!(frames, decoded.len() as u64, "{name}");
+            assert_eq!(duration, expected_duration, "{name}");
+        }
+    }
+
+    #[test]
+    fn it_errors_if_the_file_is_not_a_gif() {
+        let result = gif_animation_length(
+            File::open("src/tests/red.png").unwrap(),
+            Path::new("src/tests/red.png"),
+            &DecodeLimits::default(),
+        );
+
+        assert!(matches!(result, Err(ThumbnailError::CorruptImage { .. })));
+    }
+
+    #[test]
+    fn it_stops_counting_once_it_passes_the_limits() {
+        let path = Path::new("src/tests/animated_squares.gif");
+        let limits = DecodeLimits {
+            max_frames: Some(1),
+            ..Default::default()
+        };
+
+        let result = gif_animation_length(File::open(path).unwrap(), path, &limits);
+
+        assert!(matches!(
+            result,
+            Err(ThumbnailError::LimitsExceeded {
+                limit: LimitExceeded::Frames { frames: 2, max: 1 },
+                ..
+            })
+        ));
+    }
+}
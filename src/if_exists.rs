@@ -0,0 +1,206 @@
+use std::path::{Path, PathBuf};
+
+use clap::ValueEnum;
+use serde::Serialize;
+
+use crate::errors::ThumbnailError;
+
+/// What to do if there's already a file where we want to save a thumbnail.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum IfExists {
+    /// Replace the existing file with the new thumbnail
+    #[default]
+    Overwrite,
+
+    /// Leave the existing file alone, and don't create the thumbnail
+    Skip,
+
+    /// Fail with `ThumbnailError::OutputExists`
+    Error,
+
+    /// Save the thumbnail under a new name, e.g. `cat-1.jpg` if
+    /// `cat.jpg` already exists
+    Rename,
+}
+
+/// Decide where to save a thumbnail that we'd like to save at `path`.
+///
+/// Returns the path to write to, which is `path` unless we're renaming,
+/// or `None` if we should skip this thumbnail.
+pub fn resolve_output_path(
+    path: &Path,
+    if_exists: IfExists,
+) -> Result<Option<PathBuf>, ThumbnailError> {
+    if !path.exists() {
+        return Ok(Some(path.to_path_buf()));
+    }
+
+    match if_exists {
+        IfExists::Overwrite => Ok(Some(path.to_path_buf())),
+        IfExists::Skip => Ok(None),
+        IfExists::Error => Err(ThumbnailError::OutputExists(path.to_path_buf())),
+        IfExists::Rename => Ok(Some(first_unused_name(path))),
+    }
+}
+
+/// Decide where to save several files which belong together, e.g. the
+/// still thumbnail of a video and its preview clip.
+///
+/// This is the same as calling [`resolve_output_path`] for each path,
+/// except that if we're renaming, they all get the same suffix -- the
+/// first one which is free for every path -- so their names still match,
+/// e.g. `bars-1.jpg` and `bars-1.mp4`.
+pub fn resolve_output_paths(
+    paths: &[PathBuf],
+    if_exists: IfExists,
+) -> Result<Vec<Option<PathBuf>>, ThumbnailError> {
+    if if_exists == IfExists::Rename && paths.iter().any(|p| p.exists()) {
+        let names = (1..)
+            .map(|i| {
+                paths
+                    .iter()
+                    .map(|p| numbered_name(p, i))
+                    .collect::<Vec<_>>()
+            })
+            .find(|names| names.iter().all(|candidate| !candidate.exists()))
+            .unwrap();
+
+        return Ok(names.into_iter().map(Some).collect());
+    }
+
+    paths
+        .iter()
+        .map(|p| resolve_output_path(p, if_exists))
+        .collect()
+}
+
+/// Returns the first of `cat-1.jpg`, `cat-2.jpg`, … which doesn't exist.
+fn first_unused_name(path: &Path) -> PathBuf {
+    (1..)
+        .map(|i| numbered_name(path, i))
+        .find(|candidate| !candidate.exists())
+        .unwrap()
+}
+
+/// Returns `path` with a number added to the name, e.g. `cat-1.jpg`.
+fn numbered_name(path: &Path, i: usize) -> PathBuf {
+    let mut name = path.file_stem().unwrap_or_default().to_os_string();
+    name.push(format!("-{}", i));
+    if let Some(ext) = path.extension() {
+        name.push(".");
+        name.push(ext);
+    }
+    path.with_file_name(name)
+}
+
+#[cfg(test)]
+mod test_if_exists {
+    use std::fs;
+
+    use super::*;
+    use crate::test_utils::test_dir;
+
+    /// Create a directory with an existing `cat.jpg`, and return its path.
+    fn existing_file() -> PathBuf {
+        let dir = test_dir();
+        fs::create_dir_all(&dir).unwrap();
+
+        let path = dir.join("cat.jpg");
+        fs::write(&path, "meow").unwrap();
+        path
+    }
+
+    #[test]
+    fn it_uses_the_path_if_theres_no_file() {
+        let path = test_dir().join("cat.jpg");
+
+        for if_exists in IfExists::value_variants() {
+            assert_eq!(
+                resolve_output_path(&path, *if_exists).unwrap(),
+                Some(path.clone())
+            );
+        }
+    }
+
+    #[test]
+    fn it_overwrites_an_existing_file() {
+        let path = existing_file();
+
+        assert_eq!(
+            resolve_output_path(&path, IfExists::Overwrite).unwrap(),
+            Some(path)
+        );
+    }
+
+    #[test]
+    fn it_skips_an_existing_file() {
+        let path = existing_file();
+
+        assert_eq!(resolve_output_path(&path, IfExists::Skip).unwrap(), None);
+    }
+
+    #[test]
+    fn it_fails_if_theres_an_existing_file() {
+        let path = existing_file();
+
+        let result = resolve_output_path(&path, IfExists::Error);
+
+        assert!(matches!(result, Err(ThumbnailError::OutputExists(p)) if p == path));
+    }
+
+    #[test]
+    fn it_renames_to_avoid_an_existing_file() {
+        let path = existing_file();
+
+        assert_eq!(
+            resolve_output_path(&path, IfExists::Rename).unwrap(),
+            Some(path.with_file_name("cat-1.jpg"))
+        );
+
+        fs::write(path.with_file_name("cat-1.jpg"), "meow").unwrap();
+
+        assert_eq!(
+            resolve_output_path(&path, IfExists::Rename).unwrap(),
+            Some(path.with_file_name("cat-2.jpg"))
+        );
+    }
+
+    #[test]
+    fn it_gives_files_which_belong_together_the_same_new_name() {
+        let still = existing_file();
+        let clip = still.with_file_name("cat.mp4");
+        fs::write(still.with_file_name("cat-1.mp4"), "purr").unwrap();
+
+        assert_eq!(
+            resolve_output_paths(&[still.clone(), clip.clone()], IfExists::Rename).unwrap(),
+            vec![
+                Some(still.with_file_name("cat-2.jpg")),
+                Some(still.with_file_name("cat-2.mp4")),
+            ]
+        );
+
+        // The other policies treat each file separately.
+        assert_eq!(
+            resolve_output_paths(&[still.clone(), clip.clone()], IfExists::Skip).unwrap(),
+            vec![None, Some(clip)]
+        );
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn it_renames_a_file_whose_name_isnt_utf8() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        let dir = test_dir();
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(OsStr::from_bytes(b"c\xe4t.jpg"));
+        fs::write(&path, "meow").unwrap();
+
+        assert_eq!(
+            resolve_output_path(&path, IfExists::Rename).unwrap(),
+            Some(dir.join(OsStr::from_bytes(b"c\xe4t-1.jpg")))
+        );
+    }
+}
@@ -1,56 +1,58 @@
-use std::fs::File;
-use std::io::{BufReader, Result};
-use std::path::PathBuf;
+use std::io::{BufRead, Seek};
 
 use image::codecs::gif::GifDecoder;
 use image::AnimationDecoder;
 
-/// Returns True if a file is an animated GIF, and False otherwise.
-pub fn is_animated_gif(path: &PathBuf) -> Result<bool> {
-    let file = File::open(path)?;
-
-    let reader = BufReader::new(file);
-    let decoder = GifDecoder::new(reader);
-
-    match decoder {
-        Ok(dc) => Ok(dc.into_frames().count() > 1),
-        _ => Ok(false),
+/// Returns True if a reader contains an animated GIF, and False otherwise.
+///
+/// We stop as soon as we've seen a second frame, so we don't decode
+/// every frame of a long animation just to learn that it's animated.
+pub fn is_animated_gif<R: BufRead + Seek>(reader: R) -> bool {
+    match GifDecoder::new(reader) {
+        Ok(dc) => dc.into_frames().take(2).count() > 1,
+        _ => false,
     }
 }
 
 #[cfg(test)]
 mod test_is_animated_gif {
+    use std::fs::File;
+    use std::io::{BufReader, Result};
     use std::path::PathBuf;
 
     use super::*;
 
+    fn is_animated_gif_file(p: &PathBuf) -> Result<bool> {
+        Ok(is_animated_gif(BufReader::new(File::open(p)?)))
+    }
+
     #[test]
     fn a_png_is_not_an_animated_gif() {
         let p = PathBuf::from("src/tests/blue.png");
-        assert_eq!(is_animated_gif(&p).unwrap(), false);
+        assert!(!is_animated_gif_file(&p).unwrap());
     }
 
     #[test]
     fn a_static_gif_is_not_an_animated_gif() {
         let p = PathBuf::from("src/tests/static.gif");
-        assert_eq!(is_animated_gif(&p).unwrap(), false);
+        assert!(!is_animated_gif_file(&p).unwrap());
     }
 
     #[test]
     fn an_animated_gif_is_animated() {
         let p = PathBuf::from("src/tests/animated_squares.gif");
-        assert_eq!(is_animated_gif(&p).unwrap(), true);
+        assert!(is_animated_gif_file(&p).unwrap());
     }
 
     #[test]
-    fn a_non_image_is_not_animated_gif() {
-        let p = PathBuf::from("Cargo.toml");
-        assert_eq!(is_animated_gif(&p).unwrap(), false);
+    fn a_transparent_animated_gif_is_animated() {
+        let p = PathBuf::from("src/tests/transparent_squares.gif");
+        assert!(is_animated_gif_file(&p).unwrap());
     }
 
     #[test]
-    fn a_file_which_doesnt_exist_is_an_error() {
-        let p = PathBuf::from("does_not_exist.txt");
-        assert!(is_animated_gif(&p).is_err());
+    fn a_non_image_is_not_animated_gif() {
+        let p = PathBuf::from("Cargo.toml");
+        assert!(!is_animated_gif_file(&p).unwrap());
     }
 }
@@ -0,0 +1,94 @@
+use std::path::{Path, PathBuf};
+
+/// Returns true if two paths refer to the same file.
+///
+/// Comparing the paths directly isn't enough: `./a.jpg` and `a.jpg` are
+/// the same file, and so is a symlink and its target.  We compare the
+/// canonical forms of the paths instead.
+///
+/// The second path doesn't have to exist yet (e.g. it's a thumbnail we're
+/// about to create), but its parent directory does.
+pub fn is_same_path(a: &Path, b: &Path) -> bool {
+    if a == b {
+        return true;
+    }
+
+    match (canonicalize(a), canonicalize(b)) {
+        (Some(a), Some(b)) => a == b,
+        _ => false,
+    }
+}
+
+/// Canonicalize a path which may not exist, by canonicalizing its parent
+/// directory and adding the file name.
+fn canonicalize(path: &Path) -> Option<PathBuf> {
+    if let Ok(canonical) = path.canonicalize() {
+        return Some(canonical);
+    }
+
+    let file_name = path.file_name()?;
+    let parent = match path.parent() {
+        Some(p) if p != Path::new("") => p,
+        _ => Path::new("."),
+    };
+
+    Some(parent.canonicalize().ok()?.join(file_name))
+}
+
+#[cfg(test)]
+mod test_is_same_path {
+    use std::fs;
+
+    use super::*;
+    use crate::test_utils::test_dir;
+
+    #[test]
+    fn a_path_is_the_same_as_itself() {
+        assert!(is_same_path(
+            Path::new("src/tests/red.png"),
+            Path::new("src/tests/red.png")
+        ));
+    }
+
+    #[test]
+    fn it_ignores_redundant_components() {
+        assert!(is_same_path(
+            Path::new("src/tests/red.png"),
+            Path::new("./src/../src/tests/red.png")
+        ));
+    }
+
+    #[test]
+    fn different_files_are_different() {
+        assert!(!is_same_path(
+            Path::new("src/tests/red.png"),
+            Path::new("src/tests/blue.png")
+        ));
+    }
+
+    #[test]
+    fn the_second_path_does_not_need_to_exist() {
+        assert!(!is_same_path(
+            Path::new("src/tests/red.png"),
+            Path::new("src/tests/does_not_exist.png")
+        ));
+        assert!(is_same_path(
+            Path::new("src/tests/../tests/does_not_exist.png"),
+            Path::new("src/tests/does_not_exist.png")
+        ));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn it_follows_symlinks() {
+        let dir = test_dir();
+        fs::create_dir_all(&dir).unwrap();
+
+        let original = dir.join("original.png");
+        let link = dir.join("link.png");
+        fs::copy("src/tests/red.png", &original).unwrap();
+        std::os::unix::fs::symlink(&original, &link).unwrap();
+
+        assert!(is_same_path(&link, &original));
+    }
+}
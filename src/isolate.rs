@@ -0,0 +1,147 @@
+use std::ffi::OsString;
+use std::path::Path;
+use std::process::Command;
+use std::time::Duration;
+
+use crate::create_thumbnail::Thumbnail;
+use crate::errors::ThumbnailError;
+use crate::run_with_timeout::run_with_timeout;
+
+/// Limits on the worker process we use to create a thumbnail with
+/// `--isolate`.
+///
+/// If a malicious or broken file causes a decoder to panic, spin forever,
+/// or allocate huge amounts of memory, only the worker process is affected,
+/// and we report it as an error.
+#[derive(Clone, Debug, PartialEq)]
+pub struct IsolationLimits {
+    /// How much CPU time the worker can use before it's killed
+    pub cpu_time: Duration,
+
+    /// How much memory (address space) the worker can use, in bytes
+    pub memory: u64,
+
+    /// How long we wait for the worker before we kill it
+    pub timeout: Duration,
+}
+
+/// Create a thumbnail in a separate worker process.
+///
+/// The worker is another copy of this tool, run with the same arguments
+/// plus `--json`, so we can read information about the thumbnail from
+/// its stdout.  The `args` are the arguments passed to this process,
+/// not including the name of the program, and `path` is the image they
+/// ask for a thumbnail of, so we can name it in errors.
+///
+/// Resource limits are only applied on Unix; elsewhere we can still
+/// isolate crashes, and apply the timeout.
+pub fn create_thumbnail_in_worker(
+    path: &Path,
+    args: &[OsString],
+    limits: &IsolationLimits,
+) -> Result<Thumbnail, ThumbnailError> {
+    let crashed = |message: String| ThumbnailError::WorkerCrashed {
+        path: path.to_path_buf(),
+        message,
+    };
+    let worker_error = |e: std::io::Error| crashed(format!("unable to start: {}", e));
+
+    let mut cmd = Command::new(std::env::current_exe().map_err(worker_error)?);
+    cmd.args(worker_args(args));
+
+    // If the worker crashes, we want a one-line explanation in the
+    // error message, not a backtrace.
+    cmd.env("RUST_BACKTRACE", "0");
+
+    #[cfg(unix)]
+    apply_resource_limits(&mut cmd, limits);
+
+    let output = match run_with_timeout(cmd, None, limits.timeout).map_err(worker_error)? {
+        Some(output) => output,
+        None => {
+            return Err(ThumbnailError::WorkerTimedOut {
+                path: path.to_path_buf(),
+                timeout: limits.timeout,
+            })
+        }
+    };
+
+    let stderr = String::from_utf8_lossy(&output.stderr).trim().to_string();
+
+    // If the worker exits with one of our error codes, it ran to
+    // completion and reported an error in the usual way.  Anything else
+    // means it crashed, e.g. a panic or being killed for using too many
+    // resources.
+    match output.status.code() {
+        Some(0) => serde_json::from_slice(&output.stdout)
+            .map_err(|e| crashed(format!("unable to read worker output: {}", e))),
+        Some(exit_code @ 1..=12) => Err(ThumbnailError::WorkerFailed {
+            message: stderr,
+            exit_code,
+        }),
+        _ if stderr.is_empty() => Err(crashed(output.status.to_string())),
+        _ => Err(crashed(format!("{}: {}", output.status, stderr))),
+    }
+}
+
+/// Build the arguments for the worker process.
+///
+/// We remove `--isolate`, so the worker doesn't start a worker of its own,
+/// and make sure it prints information about the thumbnail as JSON.
+fn worker_args(args: &[OsString]) -> Vec<OsString> {
+    let mut worker_args: Vec<OsString> = args
+        .iter()
+        .filter(|a| *a != "--isolate" && *a != "--json")
+        .cloned()
+        .collect();
+
+    worker_args.push(OsString::from("--json"));
+
+    worker_args
+}
+
+/// Set the CPU and memory limits for the worker process.
+#[cfg(unix)]
+fn apply_resource_limits(cmd: &mut Command, limits: &IsolationLimits) {
+    use std::os::unix::process::CommandExt;
+
+    // The kernel sends SIGXCPU when a process reaches the soft CPU limit,
+    // and SIGKILL at the hard limit, so we give it a second's grace.
+    let cpu_seconds = limits.cpu_time.as_secs_f64().ceil() as libc::rlim_t;
+    let cpu = libc::rlimit {
+        rlim_cur: cpu_seconds,
+        rlim_max: cpu_seconds + 1,
+    };
+    let memory = libc::rlimit {
+        rlim_cur: limits.memory as libc::rlim_t,
+        rlim_max: limits.memory as libc::rlim_t,
+    };
+
+    // SAFETY: this closure runs in the child between fork and exec, so it
+    // can only call async-signal-safe functions -- which `setrlimit` is.
+    unsafe {
+        cmd.pre_exec(move || {
+            if libc::setrlimit(libc::RLIMIT_CPU, &cpu) != 0
+                || libc::setrlimit(libc::RLIMIT_AS, &memory) != 0
+            {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+}
+
+#[cfg(test)]
+mod test_isolate {
+    use super::*;
+
+    #[test]
+    fn the_worker_prints_json_and_does_not_isolate_itself() {
+        let args: Vec<OsString> = ["cat.jpg", "--isolate", "--width=100", "--json"]
+            .iter()
+            .map(OsString::from)
+            .collect();
+
+        assert_eq!(worker_args(&args), vec!["cat.jpg", "--width=100", "--json"]);
+    }
+}
@@ -0,0 +1,73 @@
+#![deny(warnings)]
+
+//! Create thumbnails of images, animated GIFs and videos.
+//!
+//! This is the library behind the `create_thumbnail` tool.  You can
+//! create a thumbnail of a file and save it in a directory with
+//! [`create_thumbnail`], or create a thumbnail of an image you already
+//! have in memory with [`create_thumbnail_from_bytes`] or
+//! [`create_thumbnail_from_reader`], which return the encoded thumbnail
+//! without touching the disk.
+//!
+//! Thumbnails are written to a temporary file and renamed into place, so
+//! you never see a partly-written thumbnail.  While a thumbnail is being
+//! created we hold an advisory lock on its path (a hidden `.<name>.lock`
+//! file next to it), so several processes can share an output directory:
+//! if two of them want to create the same thumbnail, the second waits for
+//! the first, and then reuses or replaces its thumbnail according to
+//! [`ThumbnailOptions::if_exists`].
+
+mod animated_encoder;
+mod atomic_file;
+mod colour;
+mod content_hash;
+mod create_parent_directory;
+mod create_thumbnail;
+mod create_video_thumbnail;
+mod decode_limits;
+mod decode_scaled_jpeg;
+mod errors;
+mod extract_embedded_preview;
+mod ffmpeg;
+mod get_thumbnail_dimensions;
+mod gif_animation_length;
+mod if_exists;
+mod is_animated_gif;
+mod is_same_path;
+mod isolate;
+mod name_template;
+mod options;
+mod output_lock;
+mod plan_thumbnail;
+mod probe_image;
+mod run_with_timeout;
+mod serde_path;
+mod source_image;
+mod validate_out_dir;
+mod video_encoding;
+
+#[cfg(test)]
+mod test_utils;
+
+pub use crate::animated_encoder::{
+    AnimatedEncoder, AnimationInput, AnimationJob, AnimationOutput, EncodedAnimation,
+    FfmpegEncoder, GifThumbnailEncoder,
+};
+pub use crate::atomic_file::remove_temp_files;
+pub use crate::colour::Colour;
+pub use crate::create_thumbnail::{
+    create_thumbnail, create_thumbnail_at, create_thumbnail_from_bytes,
+    create_thumbnail_from_reader, create_thumbnail_in_memory, EncodedThumbnail, Thumbnail,
+};
+pub use crate::decode_limits::{DecodeLimits, LimitExceeded, DEFAULT_MAX_ALLOC};
+pub use crate::errors::ThumbnailError;
+pub use crate::ffmpeg::FfmpegConfig;
+pub use crate::get_thumbnail_dimensions::TargetDimension;
+pub use crate::if_exists::IfExists;
+pub use crate::isolate::{create_thumbnail_in_worker, IsolationLimits};
+pub use crate::name_template::{NameTemplate, TemplateError};
+pub use crate::options::ThumbnailOptions;
+pub use crate::plan_thumbnail::{plan_thumbnail, plan_thumbnail_at, PlannedAction, ThumbnailPlan};
+pub use crate::probe_image::{probe_image, ImageInfo};
+pub use crate::validate_out_dir::validate_out_dir;
+pub use crate::video_encoding::{EvenDimensions, VideoCodec, VideoEncoding};
@@ -1,27 +1,115 @@
 #![deny(warnings)]
 
-use std::path::PathBuf;
+use std::ffi::OsString;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
-use clap::Parser;
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use image::ImageFormat;
 
-mod create_parent_directory;
-mod create_thumbnail;
-mod errors;
-mod get_thumbnail_dimensions;
-mod is_animated_gif;
+use create_thumbnail::{
+    create_thumbnail, create_thumbnail_at, create_thumbnail_in_memory, create_thumbnail_in_worker,
+    plan_thumbnail, plan_thumbnail_at, probe_image, remove_temp_files, AnimatedEncoder, Colour,
+    DecodeLimits, EvenDimensions, FfmpegConfig, FfmpegEncoder, GifThumbnailEncoder, IfExists,
+    IsolationLimits, NameTemplate, TargetDimension, ThumbnailOptions, VideoCodec, VideoEncoding,
+    DEFAULT_MAX_ALLOC,
+};
 
-use crate::create_thumbnail::create_thumbnail;
-use crate::get_thumbnail_dimensions::TargetDimension;
+/// The encoders you can use to create thumbnails of animated GIFs.
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum Encoder {
+    /// Create videos with ffmpeg
+    Ffmpeg,
+
+    /// Create resized GIFs, without any external tools
+    Gif,
+}
 
 #[derive(Debug, Parser)]
-#[clap(version, about)]
+#[clap(
+    version,
+    about,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
 struct Cli {
-    /// Path to the image to be thumbnailed
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    // Creating a thumbnail is the default, so you can pass its arguments
+    // without naming the subcommand.
+    #[command(flatten)]
+    thumbnail: Option<ThumbnailArgs>,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Create a thumbnail of an image or video (the default)
+    Thumbnail(Box<ThumbnailArgs>),
+
+    /// Print information about an image, without creating a thumbnail
+    Probe(ProbeArgs),
+}
+
+#[derive(Debug, Args)]
+struct ProbeArgs {
+    /// Path to the image to inspect, or `-` to read it from stdin
+    path: PathBuf,
+
+    /// Print the information as JSON
+    #[arg(long)]
+    json: bool,
+
+    /// Refuse to inspect images with more than this many pixels
+    #[arg(long)]
+    max_pixels: Option<u64>,
+
+    /// Refuse to read images which are bigger than this many bytes
+    #[arg(long)]
+    max_input_bytes: Option<u64>,
+
+    /// Refuse to inspect images which would use more than this many bytes
+    /// of memory to decode
+    #[arg(long, default_value_t = DEFAULT_MAX_ALLOC)]
+    max_alloc: u64,
+}
+
+#[derive(Debug, Args)]
+struct ThumbnailArgs {
+    /// Path to the image to be thumbnailed, or `-` to read it from stdin
     path: PathBuf,
 
     /// Path to the directory to save the thumbnail in
+    #[arg(long, required_unless_present = "output", conflicts_with = "output")]
+    out_dir: Option<PathBuf>,
+
+    /// Path to save the thumbnail at, rather than in a directory, or `-`
+    /// to write it to stdout.  Writing to stdout doesn't work for videos
     #[arg(long)]
-    out_dir: PathBuf,
+    output: Option<PathBuf>,
+
+    /// With --output, the format of the thumbnail, e.g. `png` or `jpg`
+    /// [default: based on the extension of --output, or the same format
+    /// as the original image when writing to stdout]
+    #[arg(long, requires = "output", value_parser = parse_format)]
+    format: Option<ImageFormat>,
+
+    /// How to name thumbnails in --out-dir, e.g. `{stem}_{width}x{height}.{ext}`.
+    /// Placeholders: {stem}, {ext}, {width}, {height}, {target}, {hash},
+    /// {hash8} and {dir}
+    #[arg(long, conflicts_with = "output")]
+    name_template: Option<NameTemplate>,
+
+    /// Save thumbnails in --out-dir under a hash of the image and the
+    /// options, e.g. `ab/cdef….jpg`, and reuse an existing thumbnail if
+    /// an identical image has already been thumbnailed
+    #[arg(long, conflicts_with_all = ["output", "name_template"])]
+    content_addressed: bool,
+
+    /// What to do if there's already a file where the thumbnail would be saved
+    #[arg(long, value_enum, default_value_t = IfExists::Overwrite)]
+    if_exists: IfExists,
 
     /// Height of the thumbnail to create
     #[arg(long)]
@@ -30,11 +118,183 @@ struct Cli {
     /// Width of the thumbnail to create
     #[arg(long)]
     width: Option<u32>,
+
+    /// For videos, the timestamp (in seconds) of the frame to use as the
+    /// thumbnail [default: 10% of the way through the video]
+    #[arg(long)]
+    frame_at: Option<f64>,
+
+    /// For videos, also create a looping MP4 preview clip of this many
+    /// seconds next to the thumbnail
+    #[arg(long)]
+    preview_seconds: Option<f64>,
+
+    /// How to create thumbnails of animated GIFs and preview clips
+    #[arg(long, value_enum, default_value_t = Encoder::Ffmpeg)]
+    encoder: Encoder,
+
+    /// Video codec for thumbnails of animated GIFs and preview clips.
+    /// This also chooses the container: MP4 for H.264 and AV1, WebM for VP9
+    #[arg(long, value_enum, default_value_t = VideoCodec::H264)]
+    codec: VideoCodec,
+
+    /// Constant Rate Factor for video thumbnails; lower values give
+    /// higher quality and bigger files [default: depends on the codec]
+    #[arg(long)]
+    crf: Option<u32>,
+
+    /// Maximum frame rate of video thumbnails
+    #[arg(long)]
+    max_fps: Option<u32>,
+
+    /// Maximum length of video thumbnails, in seconds
+    #[arg(long)]
+    max_duration: Option<f64>,
+
+    /// How to make sure video thumbnails have even dimensions, which
+    /// ffmpeg requires
+    #[arg(long, value_enum, default_value_t = EvenDimensions::Fit)]
+    even_dimensions: EvenDimensions,
+
+    /// Background colour for transparent areas of animated thumbnails,
+    /// and for padding video thumbnails to even dimensions, e.g. `#ffffff`.
+    /// Video thumbnails can't be transparent, so they default to black
+    #[arg(long)]
+    background: Option<Colour>,
+
+    /// If the image has a preview embedded in its EXIF metadata which is
+    /// big enough, create the thumbnail from that rather than the full image.
+    /// This only looks for the JPEG thumbnail in the EXIF IFD1 (of a JPEG,
+    /// TIFF, PNG or WebP), not the larger previews in camera raw files
+    #[arg(long)]
+    use_embedded_preview: bool,
+
+    /// Refuse to decode images with more than this many pixels
+    #[arg(long)]
+    max_pixels: Option<u64>,
+
+    /// Refuse to read images which are bigger than this many bytes
+    #[arg(long)]
+    max_input_bytes: Option<u64>,
+
+    /// Refuse to decode images which would use more than this many bytes
+    /// of memory
+    #[arg(long, default_value_t = DEFAULT_MAX_ALLOC)]
+    max_alloc: u64,
+
+    /// Refuse to create thumbnails of animated GIFs with more than this
+    /// many frames
+    #[arg(long)]
+    max_frames: Option<u64>,
+
+    /// Refuse to create thumbnails of animated GIFs which are longer than
+    /// this many seconds
+    #[arg(long, value_parser = parse_seconds)]
+    max_animation_duration: Option<Duration>,
+
+    /// Path to the ffmpeg binary
+    #[arg(long, env = "FFMPEG", default_value = "ffmpeg")]
+    ffmpeg_path: PathBuf,
+
+    /// Path to the ffprobe binary
+    #[arg(long, env = "FFPROBE", default_value = "ffprobe")]
+    ffprobe_path: PathBuf,
+
+    /// How long to let ffmpeg run before giving up, in seconds
+    #[arg(long, default_value_t = 300)]
+    ffmpeg_timeout: u64,
+
+    /// Print information about the thumbnail as JSON, rather than
+    /// just the path
+    #[arg(long)]
+    json: bool,
+
+    /// Print what would happen -- where each thumbnail would be saved,
+    /// how big it would be, and whether it would replace an existing
+    /// file -- without creating anything or running ffmpeg
+    #[arg(long, conflicts_with = "isolate")]
+    dry_run: bool,
+
+    /// Decode and resize the image in a separate worker process, so a
+    /// crash or hang in a decoder is reported as an error
+    #[arg(long)]
+    isolate: bool,
+
+    /// With --isolate, how many seconds of CPU time the worker can use
+    #[arg(long, default_value_t = 60)]
+    isolate_cpu_seconds: u64,
+
+    /// With --isolate, how many bytes of memory the worker can use
+    #[arg(long, default_value_t = 2 * 1024 * 1024 * 1024)]
+    isolate_memory: u64,
+
+    /// With --isolate, how many seconds to wait for the worker before
+    /// stopping it
+    #[arg(long, default_value_t = 120)]
+    isolate_timeout: u64,
+}
+
+/// Parse an image format from the command line, e.g. `png`.
+///
+/// We only accept formats we can write thumbnails in.
+fn parse_format(s: &str) -> Result<ImageFormat, String> {
+    match ImageFormat::from_extension(s) {
+        Some(format) if format.writing_enabled() => Ok(format),
+        Some(_) => Err(format!("unable to write {} images", s)),
+        None => Err(format!("unrecognised image format {}", s)),
+    }
+}
+
+/// Parse a number of seconds from the command line, e.g. `2.5`.
+fn parse_seconds(s: &str) -> Result<Duration, String> {
+    let seconds: f64 = s.parse().map_err(|e| format!("{}", e))?;
+
+    Duration::try_from_secs_f64(seconds).map_err(|e| format!("{}", e))
 }
 
 fn main() {
     let cli = Cli::parse();
 
+    match (cli.command, cli.thumbnail) {
+        (Some(Command::Probe(args)), _) => probe(args),
+        (Some(Command::Thumbnail(args)), _) => thumbnail(*args),
+        (None, Some(args)) => thumbnail(args),
+        (None, None) => unreachable!("clap requires the arguments for a thumbnail"),
+    }
+}
+
+/// Print information about an image.
+fn probe(args: ProbeArgs) {
+    let limits = DecodeLimits {
+        max_pixels: args.max_pixels,
+        max_input_bytes: args.max_input_bytes,
+        max_alloc: Some(args.max_alloc),
+        ..DecodeLimits::default()
+    };
+
+    match probe_image(&args.path, &limits) {
+        Ok(info) if args.json => println!("{}", serde_json::to_string(&info).unwrap()),
+        Ok(info) => println!("{}", info),
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(e.exit_code());
+        }
+    }
+}
+
+/// Create a thumbnail, which is what we do if you don't choose a subcommand.
+fn thumbnail(cli: ThumbnailArgs) {
+    // Thumbnails are written to temporary files and renamed into place.
+    // If we're interrupted, destructors don't run, so we delete any
+    // partly-written files ourselves.
+    //
+    // The handler isn't told which signal we received, so we exit with
+    // 130 (as if for SIGINT) for all of them; the README documents this.
+    let _ = ctrlc::set_handler(|| {
+        remove_temp_files();
+        std::process::exit(130);
+    });
+
     let target = match (cli.width, cli.height) {
         (Some(w), Some(h)) => TargetDimension::BoundingBox(w, h),
         (Some(w), None) => TargetDimension::MaxWidth(w),
@@ -43,19 +303,179 @@ fn main() {
             eprintln!(
                 "Failed to create thumbnail: you must pass at least one of --width or --height"
             );
-            std::process::exit(1);
+            std::process::exit(2);
+        }
+    };
+
+    let ffmpeg = FfmpegConfig {
+        ffmpeg: cli.ffmpeg_path,
+        ffprobe: cli.ffprobe_path,
+        timeout: Duration::from_secs(cli.ffmpeg_timeout),
+    };
+
+    let animated_encoder: Box<dyn AnimatedEncoder> = match cli.encoder {
+        Encoder::Ffmpeg => Box::new(FfmpegEncoder {
+            encoding: VideoEncoding {
+                codec: cli.codec,
+                crf: cli.crf,
+                max_fps: cli.max_fps,
+                max_duration: cli.max_duration,
+                even_dimensions: cli.even_dimensions,
+            },
+            ffmpeg: ffmpeg.clone(),
+        }),
+        Encoder::Gif => Box::new(GifThumbnailEncoder),
+    };
+
+    let options = ThumbnailOptions {
+        video_frame_at: cli.frame_at,
+        video_preview_seconds: cli.preview_seconds,
+        animated_encoder,
+        background: cli.background,
+        use_embedded_preview: cli.use_embedded_preview,
+        name_template: cli.name_template,
+        content_addressed: cli.content_addressed,
+        if_exists: cli.if_exists,
+        limits: DecodeLimits {
+            max_pixels: cli.max_pixels,
+            max_input_bytes: cli.max_input_bytes,
+            max_alloc: Some(cli.max_alloc),
+            max_frames: cli.max_frames,
+            max_animation_duration: cli.max_animation_duration,
+        },
+        ffmpeg,
+    };
+
+    let is_stdin = cli.path == Path::new("-");
+    let is_stdout = cli.output.as_deref() == Some(Path::new("-"));
+
+    if is_stdout && (cli.json || cli.isolate || cli.dry_run) {
+        eprintln!(
+            "Failed to create thumbnail: you can't use --json, --isolate or --dry-run with --output -"
+        );
+        std::process::exit(2);
+    }
+
+    // The worker process can't read our stdin.
+    if is_stdin && cli.isolate {
+        eprintln!("Failed to create thumbnail: you can't use --isolate when reading from stdin");
+        std::process::exit(2);
+    }
+
+    // If we're writing to stdout, there's no path to print -- we write
+    // the thumbnail itself.
+    if is_stdout {
+        match create_thumbnail_in_memory(&cli.path, target, cli.format, &options) {
+            Ok(thumbnail) => {
+                for warning in &thumbnail.warnings {
+                    eprintln!("Warning: {}", warning);
+                }
+
+                if let Err(e) = io::stdout().write_all(&thumbnail.bytes) {
+                    eprintln!("Failed to write thumbnail to stdout: {}", e);
+                    std::process::exit(1);
+                }
+            }
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(e.exit_code());
+            }
+        }
+
+        return;
+    }
+
+    // We need a file name for the thumbnail, which we don't have if
+    // we're reading the image from stdin.
+    if is_stdin && cli.output.is_none() {
+        eprintln!("Failed to create thumbnail: you must pass --output when reading from stdin");
+        std::process::exit(2);
+    }
+
+    if cli.dry_run {
+        let plan = match (&cli.output, &cli.out_dir) {
+            (Some(output), _) => plan_thumbnail_at(&cli.path, output, target, cli.format, &options),
+            (None, Some(out_dir)) => plan_thumbnail(&cli.path, out_dir, target, &options),
+            (None, None) => unreachable!("clap requires one of --out-dir or --output"),
+        };
+
+        match plan {
+            Ok(plan) if cli.json => println!("{}", serde_json::to_string(&plan).unwrap()),
+            Ok(plan) => println!("{}", plan),
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(e.exit_code());
+            }
+        }
+
+        return;
+    }
+
+    let result = if cli.isolate {
+        let args: Vec<OsString> = std::env::args_os().skip(1).collect();
+        let limits = IsolationLimits {
+            cpu_time: Duration::from_secs(cli.isolate_cpu_seconds),
+            memory: cli.isolate_memory,
+            timeout: Duration::from_secs(cli.isolate_timeout),
+        };
+
+        create_thumbnail_in_worker(&cli.path, &args, &limits)
+    } else {
+        match (&cli.output, &cli.out_dir) {
+            (Some(output), _) => {
+                create_thumbnail_at(&cli.path, output, target, cli.format, &options)
+            }
+            (None, Some(out_dir)) => create_thumbnail(&cli.path, out_dir, target, &options),
+            (None, None) => unreachable!("clap requires one of --out-dir or --output"),
         }
     };
 
-    match create_thumbnail(&cli.path, &cli.out_dir, target) {
-        Ok(thumbnail_path) => print!("{}", thumbnail_path.display()),
+    if let Ok(thumbnail) = &result {
+        for warning in &thumbnail.warnings {
+            eprintln!("Warning: {}", warning);
+        }
+
+        if thumbnail.skipped {
+            eprintln!(
+                "Skipped {}: the file already exists",
+                thumbnail.path.display()
+            );
+        }
+    }
+
+    match result {
+        Ok(thumbnail) if cli.json => println!("{}", serde_json::to_string(&thumbnail).unwrap()),
+        Ok(thumbnail) => print_path(&thumbnail.path),
         Err(e) => {
             eprintln!("{}", e);
-            std::process::exit(1);
+            std::process::exit(e.exit_code());
         }
     };
 }
 
+/// Print a path to stdout exactly as it is.
+///
+/// `Path::display()` replaces anything that isn't valid UTF-8, but then
+/// a script couldn't use the path to find the thumbnail, so on Unix we
+/// print the raw bytes of the path.
+fn print_path(path: &Path) {
+    #[cfg(unix)]
+    let bytes = {
+        use std::os::unix::ffi::OsStrExt;
+        path.as_os_str().as_bytes()
+    };
+
+    #[cfg(not(unix))]
+    let bytes = path.to_string_lossy();
+    #[cfg(not(unix))]
+    let bytes = bytes.as_bytes();
+
+    if let Err(e) = io::stdout().write_all(bytes) {
+        eprintln!("Failed to write thumbnail path to stdout: {}", e);
+        std::process::exit(1);
+    }
+}
+
 #[expect(
     deprecated,
     reason = "cargo_bin is deprecated, cargo_bin! is not, `use` does not differentiate them. See https://github.com/assert-rs/assert_cmd/issues/258"
@@ -73,7 +493,7 @@ mod test_cli {
     fn it_creates_a_thumbnail_with_max_width() {
         Command::cargo_bin("create_thumbnail")
             .unwrap()
-            .args(&["src/tests/red.png", "--width=50", "--out-dir=/tmp"])
+            .args(["src/tests/red.png", "--width=50", "--out-dir=/tmp"])
             .assert()
             .success()
             .stdout("/tmp/red.png")
@@ -86,7 +506,7 @@ mod test_cli {
     fn it_creates_a_thumbnail_with_max_height() {
         Command::cargo_bin("create_thumbnail")
             .unwrap()
-            .args(&["src/tests/noise.jpg", "--height=128", "--out-dir=/tmp"])
+            .args(["src/tests/noise.jpg", "--height=128", "--out-dir=/tmp"])
             .assert()
             .success()
             .stdout("/tmp/noise.jpg")
@@ -99,7 +519,7 @@ mod test_cli {
     fn it_creates_a_thumbnail_with_a_bounding_box() {
         Command::cargo_bin("create_thumbnail")
             .unwrap()
-            .args(&[
+            .args([
                 "src/tests/noise.jpg",
                 "--width=64",
                 "--height=64",
@@ -113,14 +533,211 @@ mod test_cli {
         assert_eq!(get_dimensions(&PathBuf::from("/tmp/noise.jpg")), (32, 64));
     }
 
+    #[test]
+    fn it_prints_json_if_asked() {
+        let out_dir = crate::test_utils::test_dir();
+
+        Command::cargo_bin("create_thumbnail")
+            .unwrap()
+            .args(["src/tests/red.png", "--width=50", "--json", "--out-dir"])
+            .arg(&out_dir)
+            .assert()
+            .success()
+            .stdout(format!(
+                "{{\"path\":\"{}\",\"width\":50,\"height\":100}}\n",
+                out_dir.join("red.png").display()
+            ))
+            .stderr("");
+    }
+
+    #[test]
+    fn it_fails_if_ffmpeg_is_not_installed() {
+        Command::cargo_bin("create_thumbnail")
+            .unwrap()
+            .args([
+                "src/tests/animated_squares.gif",
+                "--width=16",
+                "--ffmpeg-path=/does/not/exist/ffmpeg",
+            ])
+            .arg("--out-dir")
+            .arg(crate::test_utils::test_dir())
+            .assert()
+            .failure()
+            .code(11)
+            .stdout("")
+            .stderr(
+                "Unable to find /does/not/exist/ffmpeg to read src/tests/animated_squares.gif; \
+                 is ffmpeg installed?\n",
+            );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn it_cleans_up_if_it_is_interrupted() {
+        use std::os::unix::fs::PermissionsExt;
+        use std::time::{Duration, Instant};
+
+        // This fake ffmpeg never finishes, so we can interrupt the tool
+        // while it's holding the lock on the thumbnail.
+        let out_dir = crate::test_utils::test_dir();
+        std::fs::create_dir_all(&out_dir).unwrap();
+        let fake_ffmpeg = out_dir.join("ffmpeg");
+        std::fs::write(&fake_ffmpeg, "#!/bin/sh\nsleep 10\n").unwrap();
+        std::fs::set_permissions(&fake_ffmpeg, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let mut child =
+            std::process::Command::new(assert_cmd::cargo::cargo_bin("create_thumbnail"))
+                .args(["src/tests/animated_squares.gif", "--width=16"])
+                .arg("--ffmpeg-path")
+                .arg(&fake_ffmpeg)
+                .arg("--out-dir")
+                .arg(&out_dir)
+                .spawn()
+                .unwrap();
+
+        let lock_path = out_dir.join(".animated_squares.mp4.lock");
+        let start = Instant::now();
+        while !lock_path.exists() {
+            assert!(
+                start.elapsed() < Duration::from_secs(5),
+                "never took the lock"
+            );
+            std::thread::sleep(Duration::from_millis(10));
+        }
+
+        unsafe { libc::kill(child.id() as libc::pid_t, libc::SIGTERM) };
+        let status = child.wait().unwrap();
+
+        assert_eq!(status.code(), Some(130));
+        assert!(!lock_path.exists());
+    }
+
+    #[test]
+    fn it_keeps_the_timing_of_an_animated_gif() {
+        if !crate::test_utils::ffmpeg_is_installed() {
+            return;
+        }
+
+        let output = crate::test_utils::test_dir().join("transparent_squares.mp4");
+
+        Command::cargo_bin("create_thumbnail")
+            .unwrap()
+            .args([
+                "src/tests/transparent_squares.gif",
+                "--width=16",
+                "--background=#ffffff",
+                "--output",
+            ])
+            .arg(&output)
+            .assert()
+            .success();
+
+        // The GIF has 2 frames, and lasts 0.4 seconds.
+        let (frames, duration) = crate::test_utils::get_video_length(&output);
+        assert_eq!(frames, 2);
+        assert!((duration - 0.4).abs() < 0.05, "duration = {}", duration);
+    }
+
+    #[test]
+    fn it_refuses_to_decode_a_decompression_bomb() {
+        // This PNG is less than 1KB, but it claims to be 100000×100000
+        Command::cargo_bin("create_thumbnail")
+            .unwrap()
+            .args(["src/tests/bomb.png", "--width=16"])
+            .arg("--out-dir")
+            .arg(crate::test_utils::test_dir())
+            .assert()
+            .failure()
+            .code(7)
+            .stdout("")
+            .stderr("Image src/tests/bomb.png is too big to thumbnail: decoding it would use 10000000000 bytes of memory, but the limit is 536870912\n");
+    }
+
+    #[test]
+    fn it_fails_if_the_image_has_too_many_pixels() {
+        Command::cargo_bin("create_thumbnail")
+            .unwrap()
+            .args(["src/tests/red.png", "--width=16", "--max-pixels=10000"])
+            .arg("--out-dir")
+            .arg(crate::test_utils::test_dir())
+            .assert()
+            .failure()
+            .code(7)
+            .stdout("")
+            .stderr("Image src/tests/red.png is too big to thumbnail: it has 20000 pixels, but the limit is 10000\n");
+    }
+
+    #[test]
+    fn it_creates_a_thumbnail_in_a_worker_process() {
+        let out_dir = crate::test_utils::test_dir();
+
+        Command::cargo_bin("create_thumbnail")
+            .unwrap()
+            .args(["src/tests/red.png", "--width=50", "--isolate", "--json"])
+            .arg("--out-dir")
+            .arg(&out_dir)
+            .assert()
+            .success()
+            .stdout(format!(
+                "{{\"path\":\"{}/red.png\",\"width\":50,\"height\":100}}\n",
+                out_dir.display()
+            ))
+            .stderr("");
+    }
+
+    #[test]
+    fn it_reports_errors_from_a_worker_process() {
+        Command::cargo_bin("create_thumbnail")
+            .unwrap()
+            .args([
+                "doesnotexist.jpg",
+                "--width=50",
+                "--isolate",
+                "--out-dir=/tmp",
+            ])
+            .assert()
+            .failure()
+            .code(3)
+            .stdout("")
+            .stderr("Unable to find doesnotexist.jpg: No such file or directory (os error 2)\n");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn it_survives_a_worker_process_running_out_of_memory() {
+        // We turn off the decoder limits, so the worker tries to allocate
+        // 10GB for the decompression bomb, and crashes.
+        Command::cargo_bin("create_thumbnail")
+            .unwrap()
+            .args([
+                "src/tests/bomb.png",
+                "--width=50",
+                "--isolate",
+                "--isolate-memory=200000000",
+                "--max-alloc=100000000000",
+            ])
+            .arg("--out-dir")
+            .arg(crate::test_utils::test_dir())
+            .assert()
+            .failure()
+            .code(13)
+            .stdout("")
+            .stderr(predicate::str::starts_with(
+                "The worker process crashed on src/tests/bomb.png: signal: 6 (SIGABRT)",
+            ))
+            .stderr(predicate::str::contains(
+                "memory allocation of 10000000000 bytes failed",
+            ));
+    }
+
     #[test]
     fn it_fails_if_you_pass_neither_width_nor_height() {
         Command::cargo_bin("create_thumbnail")
             .unwrap()
-            .args(&["src/tests/red.png", "--out-dir=/tmp"])
+            .args(["src/tests/red.png", "--out-dir=/tmp"])
             .assert()
             .failure()
-            .code(1)
+            .code(2)
             .stdout("")
             .stderr(
                 "Failed to create thumbnail: you must pass at least one of --width or --height\n",
@@ -131,52 +748,486 @@ mod test_cli {
     fn it_fails_if_you_pass_a_non_existent_file() {
         Command::cargo_bin("create_thumbnail")
             .unwrap()
-            .args(&["doesnotexist.txt", "--width=50", "--out-dir=/tmp"])
+            .args(["doesnotexist.txt", "--width=50", "--out-dir=/tmp"])
             .assert()
             .failure()
-            .code(1)
+            .code(3)
             .stdout("")
-            .stderr("Failed to open image: No such file or directory (os error 2)\n");
+            .stderr("Unable to find doesnotexist.txt: No such file or directory (os error 2)\n");
     }
 
     #[test]
     fn it_fails_if_you_pass_a_non_image() {
         Command::cargo_bin("create_thumbnail")
             .unwrap()
-            .args(&["Cargo.toml", "--width=50", "--out-dir=/tmp"])
+            .args(["Cargo.toml", "--width=50", "--out-dir=/tmp"])
             .assert()
             .failure()
-            .code(1)
+            .code(5)
             .stdout("")
-            .stderr("Failed to open image: The file extension `.\"toml\"` was not recognized as an image format\n");
+            .stderr("Failed to open image Cargo.toml: The image format could not be determined\n");
+    }
+
+    #[test]
+    fn it_warns_if_the_extension_does_not_match_the_image() {
+        let tmp_dir = crate::test_utils::test_dir();
+        std::fs::create_dir_all(&tmp_dir).unwrap();
+        let img_path = tmp_dir.join("red.jpg");
+        std::fs::copy("src/tests/red.png", &img_path).unwrap();
+
+        let out_dir = tmp_dir.join("thumbnails");
+
+        Command::cargo_bin("create_thumbnail")
+            .unwrap()
+            .arg(&img_path)
+            .arg("--width=50")
+            .arg("--out-dir")
+            .arg(&out_dir)
+            .assert()
+            .success()
+            .stdout(out_dir.join("red.png").display().to_string())
+            .stderr(format!(
+                "Warning: {} looks like a PNG image, but has the extension .jpg\n",
+                img_path.display()
+            ));
+    }
+
+    #[test]
+    fn it_reads_from_stdin_and_writes_to_stdout() {
+        let output = Command::cargo_bin("create_thumbnail")
+            .unwrap()
+            .args(["-", "--width=50", "--output", "-"])
+            .write_stdin(std::fs::read("src/tests/red.png").unwrap())
+            .assert()
+            .success()
+            .stderr("")
+            .get_output()
+            .stdout
+            .clone();
+
+        let img = image::load_from_memory_with_format(&output, image::ImageFormat::Png).unwrap();
+        assert_eq!(image::GenericImageView::dimensions(&img), (50, 100));
+    }
+
+    #[test]
+    fn it_writes_to_stdout_in_the_chosen_format() {
+        let output = Command::cargo_bin("create_thumbnail")
+            .unwrap()
+            .args([
+                "src/tests/red.png",
+                "--width=50",
+                "--output=-",
+                "--format=jpg",
+            ])
+            .assert()
+            .success()
+            .stderr("")
+            .get_output()
+            .stdout
+            .clone();
+
+        assert_eq!(
+            image::guess_format(&output).unwrap(),
+            image::ImageFormat::Jpeg
+        );
+    }
+
+    #[test]
+    fn it_fails_if_stdin_is_not_an_image() {
+        Command::cargo_bin("create_thumbnail")
+            .unwrap()
+            .args(["-", "--width=50", "--output", "-"])
+            .write_stdin("hello world")
+            .assert()
+            .failure()
+            .code(5)
+            .stdout("")
+            .stderr("Failed to open image: The image format could not be determined\n");
+    }
+
+    #[test]
+    fn it_needs_output_when_reading_from_stdin() {
+        Command::cargo_bin("create_thumbnail")
+            .unwrap()
+            .args(["-", "--width=50", "--out-dir=/tmp"])
+            .write_stdin(std::fs::read("src/tests/red.png").unwrap())
+            .assert()
+            .failure()
+            .code(2)
+            .stdout("")
+            .stderr("Failed to create thumbnail: you must pass --output when reading from stdin\n");
+    }
+
+    #[test]
+    fn it_rejects_a_format_it_cannot_write() {
+        Command::cargo_bin("create_thumbnail")
+            .unwrap()
+            .args([
+                "src/tests/red.png",
+                "--width=50",
+                "--output=-",
+                "--format=xyz",
+            ])
+            .assert()
+            .failure()
+            .stdout("")
+            .stderr(predicate::str::contains("unrecognised image format xyz"));
+    }
+
+    #[test]
+    fn it_saves_the_thumbnail_at_an_exact_path() {
+        let out_dir = crate::test_utils::test_dir();
+        let output = out_dir.join("nested/small_red.jpg");
+
+        Command::cargo_bin("create_thumbnail")
+            .unwrap()
+            .args(["src/tests/red.png", "--width=50", "--output"])
+            .arg(&output)
+            .assert()
+            .success()
+            .stdout(output.display().to_string())
+            .stderr("");
+
+        assert_eq!(get_dimensions(&output), (50, 100));
+        assert_eq!(
+            image::ImageReader::open(&output)
+                .unwrap()
+                .with_guessed_format()
+                .unwrap()
+                .format(),
+            Some(image::ImageFormat::Jpeg)
+        );
+    }
+
+    #[test]
+    fn it_reads_from_stdin_and_saves_to_a_file() {
+        let output = crate::test_utils::test_dir().join("red.png");
+
+        Command::cargo_bin("create_thumbnail")
+            .unwrap()
+            .args(["-", "--width=50", "--output"])
+            .arg(&output)
+            .write_stdin(std::fs::read("src/tests/red.png").unwrap())
+            .assert()
+            .success()
+            .stderr("");
+
+        assert_eq!(get_dimensions(&output), (50, 100));
+    }
+
+    #[test]
+    fn it_needs_a_format_if_the_output_has_no_extension() {
+        let output = crate::test_utils::test_dir().join("thumbnail");
+
+        Command::cargo_bin("create_thumbnail")
+            .unwrap()
+            .args(["src/tests/red.png", "--width=50", "--output"])
+            .arg(&output)
+            .assert()
+            .failure()
+            .code(2)
+            .stdout("")
+            .stderr(format!(
+                "Failed to create thumbnail: unable to tell what format to use for {}; use --format to choose one\n",
+                output.display()
+            ));
+    }
+
+    #[test]
+    fn it_will_not_overwrite_the_original_through_a_different_path() {
+        let tmp_dir = crate::test_utils::test_dir();
+        std::fs::create_dir_all(&tmp_dir).unwrap();
+        let img_path = tmp_dir.join("red.png");
+        std::fs::copy("src/tests/red.png", &img_path).unwrap();
+
+        Command::cargo_bin("create_thumbnail")
+            .unwrap()
+            .arg(&img_path)
+            .args(["--width=50", "--output"])
+            .arg(tmp_dir.join(".").join("red.png"))
+            .assert()
+            .failure()
+            .code(2)
+            .stdout("")
+            .stderr(format!(
+                "Cannot write thumbnail to {}: it is the original image\n",
+                tmp_dir.join(".").join("red.png").display()
+            ));
+
+        assert_eq!(get_dimensions(&img_path), (100, 200));
+    }
+
+    #[test]
+    fn it_names_thumbnails_with_a_template() {
+        let out_dir = crate::test_utils::test_dir();
+
+        Command::cargo_bin("create_thumbnail")
+            .unwrap()
+            .args([
+                "src/tests/noise.jpg",
+                "--height=64",
+                "--name-template={stem}_{width}x{height}.{ext}",
+                "--out-dir",
+            ])
+            .arg(&out_dir)
+            .assert()
+            .success()
+            .stdout(out_dir.join("noise_32x64.jpg").display().to_string())
+            .stderr("");
+    }
+
+    #[test]
+    fn it_checks_the_template_before_reading_the_image() {
+        Command::cargo_bin("create_thumbnail")
+            .unwrap()
+            .args([
+                "src/tests/does_not_exist.jpg",
+                "--width=50",
+                "--name-template={name}.{ext}",
+                "--out-dir=/tmp",
+            ])
+            .assert()
+            .failure()
+            .code(2)
+            .stdout("")
+            .stderr(predicate::str::contains("unknown placeholder {name}"));
+    }
+
+    #[test]
+    fn it_prints_the_content_addressed_path() {
+        let out_dir = crate::test_utils::test_dir();
+
+        let run = || {
+            Command::cargo_bin("create_thumbnail")
+                .unwrap()
+                .args(["src/tests/red.png", "--width=50", "--content-addressed"])
+                .arg("--out-dir")
+                .arg(&out_dir)
+                .assert()
+                .success()
+                .stderr("")
+                .get_output()
+                .stdout
+                .clone()
+        };
+
+        let first = String::from_utf8(run()).unwrap();
+        let second = String::from_utf8(run()).unwrap();
+
+        assert_eq!(first, second);
+        assert!(first.starts_with(&out_dir.display().to_string()));
+        assert!(first.ends_with(".png"));
+        assert_eq!(get_dimensions(&PathBuf::from(first)), (50, 100));
+    }
+
+    #[test]
+    fn it_skips_an_existing_thumbnail() {
+        let out_dir = crate::test_utils::test_dir();
+        std::fs::create_dir_all(&out_dir).unwrap();
+        let thumbnail_path = out_dir.join("red.png");
+        std::fs::write(&thumbnail_path, "not a thumbnail").unwrap();
+
+        Command::cargo_bin("create_thumbnail")
+            .unwrap()
+            .args(["src/tests/red.png", "--width=50", "--if-exists=skip"])
+            .arg("--out-dir")
+            .arg(&out_dir)
+            .assert()
+            .success()
+            .stdout(thumbnail_path.display().to_string())
+            .stderr(format!(
+                "Skipped {}: the file already exists\n",
+                thumbnail_path.display()
+            ));
+
+        assert_eq!(
+            std::fs::read_to_string(&thumbnail_path).unwrap(),
+            "not a thumbnail"
+        );
+    }
+
+    #[test]
+    fn it_fails_if_the_thumbnail_exists() {
+        let out_dir = crate::test_utils::test_dir();
+        std::fs::create_dir_all(&out_dir).unwrap();
+        let thumbnail_path = out_dir.join("red.png");
+        std::fs::write(&thumbnail_path, "not a thumbnail").unwrap();
+
+        Command::cargo_bin("create_thumbnail")
+            .unwrap()
+            .args(["src/tests/red.png", "--width=50", "--if-exists=error"])
+            .arg("--out-dir")
+            .arg(&out_dir)
+            .assert()
+            .failure()
+            .code(9)
+            .stdout("")
+            .stderr(format!(
+                "Cannot write thumbnail to {}: the file already exists\n",
+                thumbnail_path.display()
+            ));
+    }
+
+    #[test]
+    fn it_renames_the_thumbnail_if_it_exists() {
+        let out_dir = crate::test_utils::test_dir();
+        std::fs::create_dir_all(&out_dir).unwrap();
+        std::fs::write(out_dir.join("red.png"), "not a thumbnail").unwrap();
+
+        Command::cargo_bin("create_thumbnail")
+            .unwrap()
+            .args(["src/tests/red.png", "--width=50", "--if-exists=rename"])
+            .arg("--output")
+            .arg(out_dir.join("red.png"))
+            .assert()
+            .success()
+            .stdout(out_dir.join("red-1.png").display().to_string())
+            .stderr("");
+
+        assert_eq!(get_dimensions(&out_dir.join("red-1.png")), (50, 100));
+    }
+
+    #[test]
+    fn it_prints_a_plan_without_creating_anything() {
+        let out_dir = crate::test_utils::test_dir();
+
+        Command::cargo_bin("create_thumbnail")
+            .unwrap()
+            .args(["src/tests/red.png", "--width=50", "--dry-run", "--out-dir"])
+            .arg(&out_dir)
+            .assert()
+            .success()
+            .stdout(format!(
+                "src/tests/red.png (100x200) -> {} (50x100): create\n",
+                out_dir.join("red.png").display()
+            ))
+            .stderr("");
+
+        assert!(!out_dir.exists());
+    }
+
+    #[test]
+    fn it_prints_a_plan_as_json() {
+        let output = crate::test_utils::test_dir().join("squares.mp4");
+
+        let stdout = Command::cargo_bin("create_thumbnail")
+            .unwrap()
+            .args([
+                "src/tests/animated_squares.gif",
+                "--width=16",
+                "--dry-run",
+                "--json",
+                "--output",
+            ])
+            .arg(&output)
+            .assert()
+            .success()
+            .stderr("")
+            .get_output()
+            .stdout
+            .clone();
+
+        let plan: serde_json::Value = serde_json::from_slice(&stdout).unwrap();
+        assert_eq!(plan["is_animated"], true);
+        assert_eq!(plan["source_width"], 32);
+        assert_eq!(plan["width"], 16);
+        assert_eq!(plan["action"], "create");
+        assert!(!output.exists());
+    }
+
+    #[test]
+    fn it_accepts_the_thumbnail_subcommand() {
+        let out_dir = crate::test_utils::test_dir();
+
+        Command::cargo_bin("create_thumbnail")
+            .unwrap()
+            .args(["thumbnail", "src/tests/red.png", "--width=50", "--out-dir"])
+            .arg(&out_dir)
+            .assert()
+            .success()
+            .stdout(out_dir.join("red.png").display().to_string())
+            .stderr("");
+
+        assert_eq!(get_dimensions(&out_dir.join("red.png")), (50, 100));
+    }
+
+    #[test]
+    fn it_probes_an_image() {
+        Command::cargo_bin("create_thumbnail")
+            .unwrap()
+            .args(["probe", "src/tests/red.png"])
+            .assert()
+            .success()
+            .stdout(predicate::str::contains("format:      PNG\n"))
+            .stdout(predicate::str::contains("dimensions:  100x200\n"))
+            .stdout(predicate::str::contains("frames:      1\n"))
+            .stderr("");
+    }
+
+    #[test]
+    fn it_probes_an_image_as_json() {
+        let stdout = Command::cargo_bin("create_thumbnail")
+            .unwrap()
+            .args(["probe", "--json", "src/tests/animated_squares.gif"])
+            .assert()
+            .success()
+            .stderr("")
+            .get_output()
+            .stdout
+            .clone();
+
+        let info: serde_json::Value = serde_json::from_slice(&stdout).unwrap();
+        assert_eq!(info["format"], "GIF");
+        assert_eq!(info["width"], 32);
+        assert_eq!(info["is_animated"], true);
+        assert_eq!(info["frames"], 8);
+    }
+
+    #[test]
+    fn it_fails_to_probe_something_thats_not_an_image() {
+        Command::cargo_bin("create_thumbnail")
+            .unwrap()
+            .args(["probe", "README.md"])
+            .assert()
+            .failure()
+            .code(5)
+            .stdout("");
     }
 
-    // TODO: Improve this error message.
-    //
-    // It's good to know the tool won't completely break when this happens, but ideally
-    // we'd return a more meaningful error message in this case.
     #[test]
     fn it_fails_if_out_dir_is_a_file() {
         Command::cargo_bin("create_thumbnail")
             .unwrap()
-            .args(&["src/images/noise.jpg", "--width=50", "--out-dir=README.md"])
+            .args(["src/tests/noise.jpg", "--width=50", "--out-dir=README.md"])
             .assert()
             .failure()
-            .code(1)
+            .code(8)
             .stdout("")
-            .stderr("I/O error: File exists (os error 17)\n");
+            .stderr("Cannot save thumbnails in README.md: it is not a directory\n");
     }
 
     #[test]
     fn it_fails_if_you_try_to_overwrite_the_original_file() {
+        let tmp_dir = crate::test_utils::test_dir();
+        std::fs::create_dir_all(&tmp_dir).unwrap();
+        let img_path = tmp_dir.join("noise.jpg");
+        std::fs::copy("src/tests/noise.jpg", &img_path).unwrap();
+
         Command::cargo_bin("create_thumbnail")
             .unwrap()
-            .args(&["src/images/noise.jpg", "--width=50", "--out-dir=src/images"])
+            .arg(&img_path)
+            .arg("--width=50")
+            .arg(format!("--out-dir={}", tmp_dir.display()))
             .assert()
             .failure()
-            .code(1)
+            .code(2)
             .stdout("")
-            .stderr("Cannot write thumbnail to the same path as the original image\n");
+            .stderr(format!(
+                "Cannot write thumbnail to {}: it is the original image\n",
+                img_path.display()
+            ));
+
+        assert_eq!(get_dimensions(&img_path), (128, 256));
     }
 
     #[test]
@@ -198,7 +1249,7 @@ mod test_cli {
     fn it_prints_the_help() {
         // Match strings like `create_thumbnail 1.2.3`
         let is_help_text =
-            predicate::str::is_match(r"create_thumbnail \[OPTIONS\] --out-dir").unwrap();
+            predicate::str::is_match(r"create_thumbnail \[OPTIONS\] <PATH>").unwrap();
 
         Command::cargo_bin("create_thumbnail")
             .unwrap()
@@ -208,27 +1259,37 @@ mod test_cli {
             .stdout(is_help_text)
             .stderr("");
     }
-}
 
-#[cfg(test)]
-pub mod test_utils {
-    use std::path::PathBuf;
-
-    use image::GenericImageView;
-
-    /// Return a path to a temporary directory to use for testing.
-    ///
-    /// This function does *not* create the directory, just the path.
-    pub fn test_dir() -> PathBuf {
-        let tmp_dir = tempfile::tempdir().unwrap();
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn it_prints_a_path_which_isnt_utf8_exactly() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
 
-        tmp_dir.path().to_owned()
-    }
+        let dir = crate::test_utils::test_dir();
+        std::fs::create_dir_all(&dir).unwrap();
+        let img_path = dir.join(OsStr::from_bytes(b"c\xe4t.png"));
+        std::fs::copy("src/tests/red.png", &img_path).unwrap();
 
-    /// Return the dimensions for an image.
-    pub fn get_dimensions(path: &PathBuf) -> (u32, u32) {
-        let img = image::open(path).unwrap();
+        let out_dir = crate::test_utils::test_dir();
 
-        img.dimensions()
+        Command::cargo_bin("create_thumbnail")
+            .unwrap()
+            .arg(&img_path)
+            .args(["--width=50", "--isolate", "--out-dir"])
+            .arg(&out_dir)
+            .assert()
+            .success()
+            .stdout(
+                out_dir
+                    .join(OsStr::from_bytes(b"c\xe4t.png"))
+                    .as_os_str()
+                    .as_bytes()
+                    .to_vec(),
+            )
+            .stderr("");
     }
 }
+
+#[cfg(test)]
+mod test_utils;
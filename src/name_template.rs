@@ -0,0 +1,364 @@
+use std::ffi::OsString;
+use std::fmt;
+use std::path::{Component, Path, PathBuf};
+use std::str::FromStr;
+
+use crate::get_thumbnail_dimensions::TargetDimension;
+
+/// A template for the names of thumbnails, e.g. `{stem}_{width}x{height}.{ext}`.
+///
+/// The template is parsed and checked when it's created, so a mistake
+/// in the template is reported before we read any images.  These are
+/// the placeholders you can use:
+///
+/// *   `{stem}` -- the name of the original file, without its extension
+/// *   `{ext}` -- the extension of the thumbnail, e.g. `jpg` or `mp4`
+/// *   `{width}` and `{height}` -- the dimensions of the thumbnail
+/// *   `{target}` -- the size you asked for, e.g. `w100`, `h250` or `100x250`
+/// *   `{hash}` -- the SHA-256 hash of the original file
+/// *   `{hash8}` -- the first 8 characters of `{hash}`
+/// *   `{dir}` -- the directory of the original file, e.g. `photos/2024`
+///     for `photos/2024/cat.jpg`, so you can mirror the original layout
+///
+/// Use `{{` and `}}` for literal braces.
+#[derive(Clone, Debug, PartialEq)]
+pub struct NameTemplate {
+    parts: Vec<Part>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Part {
+    Literal(String),
+    Placeholder(Placeholder),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Placeholder {
+    Stem,
+    Ext,
+    Width,
+    Height,
+    Target,
+    Hash,
+    Hash8,
+    Dir,
+}
+
+/// The values we substitute into a `NameTemplate`.
+pub struct NameValues<'a> {
+    /// Path to the original file
+    pub path: &'a Path,
+
+    /// The extension of the thumbnail
+    pub extension: &'a str,
+
+    /// The dimensions of the thumbnail
+    pub dimensions: (u32, u32),
+
+    /// The size the caller asked for
+    pub target: TargetDimension,
+
+    /// The hex-encoded SHA-256 hash of the original file, if the
+    /// template needs it
+    pub hash: Option<String>,
+}
+
+/// Why a name template is invalid.
+#[derive(Debug, PartialEq)]
+pub enum TemplateError {
+    Empty,
+    UnknownPlaceholder(String),
+    UnclosedBrace,
+    UnmatchedBrace,
+    NotRelative,
+}
+
+impl fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TemplateError::Empty => write!(f, "the template is empty"),
+            TemplateError::UnknownPlaceholder(name) => {
+                write!(f, "unknown placeholder {{{}}}", name)
+            }
+            TemplateError::UnclosedBrace => {
+                write!(f, "a {{ is never closed; use {{{{ for a literal {{")
+            }
+            TemplateError::UnmatchedBrace => {
+                write!(f, "a }} is never opened; use }}}} for a literal }}")
+            }
+            TemplateError::NotRelative => write!(
+                f,
+                "the template must be a relative path, and can't contain `..`"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for TemplateError {}
+
+impl FromStr for NameTemplate {
+    type Err = TemplateError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.is_empty() {
+            return Err(TemplateError::Empty);
+        }
+
+        let mut parts = vec![];
+        let mut literal = String::new();
+        let mut chars = s.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '}' => return Err(TemplateError::UnmatchedBrace),
+                '{' => {
+                    let mut name = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => name.push(c),
+                            None => return Err(TemplateError::UnclosedBrace),
+                        }
+                    }
+
+                    if !literal.is_empty() {
+                        parts.push(Part::Literal(std::mem::take(&mut literal)));
+                    }
+                    parts.push(Part::Placeholder(parse_placeholder(&name)?));
+                }
+                c => literal.push(c),
+            }
+        }
+
+        if !literal.is_empty() {
+            parts.push(Part::Literal(literal));
+        }
+
+        // The thumbnail has to end up inside the output directory, so the
+        // literal parts can't make the path absolute or climb out of it.
+        let skeleton: String = parts
+            .iter()
+            .map(|p| match p {
+                Part::Literal(s) => s.as_str(),
+                Part::Placeholder(_) => "x",
+            })
+            .collect();
+        if !is_relative_and_contained(Path::new(&skeleton)) {
+            return Err(TemplateError::NotRelative);
+        }
+
+        Ok(NameTemplate { parts })
+    }
+}
+
+fn parse_placeholder(name: &str) -> Result<Placeholder, TemplateError> {
+    match name {
+        "stem" => Ok(Placeholder::Stem),
+        "ext" => Ok(Placeholder::Ext),
+        "width" => Ok(Placeholder::Width),
+        "height" => Ok(Placeholder::Height),
+        "target" => Ok(Placeholder::Target),
+        "hash" => Ok(Placeholder::Hash),
+        "hash8" => Ok(Placeholder::Hash8),
+        "dir" => Ok(Placeholder::Dir),
+        _ => Err(TemplateError::UnknownPlaceholder(name.to_string())),
+    }
+}
+
+/// Returns true if a path only has normal components, i.e. it isn't
+/// absolute and doesn't contain `..`.
+fn is_relative_and_contained(path: &Path) -> bool {
+    path.components()
+        .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+}
+
+impl NameTemplate {
+    /// Whether the template uses the hash of the original file.
+    ///
+    /// Hashing a big file is expensive, so we only do it if we need to.
+    pub fn uses_hash(&self) -> bool {
+        self.parts
+            .iter()
+            .any(|p| matches!(p, Part::Placeholder(Placeholder::Hash | Placeholder::Hash8)))
+    }
+
+    /// Fill in the placeholders in the template, and return the path of
+    /// the thumbnail relative to the output directory.
+    pub fn render(&self, values: &NameValues) -> PathBuf {
+        let mut name = OsString::new();
+
+        for part in &self.parts {
+            match part {
+                Part::Literal(s) => name.push(s),
+                Part::Placeholder(p) => name.push(render_placeholder(*p, values)),
+            }
+        }
+
+        // If a placeholder is empty, e.g. `{dir}` for a file in the
+        // current directory, we can end up with an empty component or a
+        // leading slash, which we tidy up.
+        Path::new(&name)
+            .components()
+            .filter(|c| matches!(c, Component::Normal(_)))
+            .collect()
+    }
+}
+
+/// Returns the value of a placeholder.
+///
+/// The stem and directory come from the original path, which may not be
+/// valid UTF-8, so we keep them as an `OsString` rather than mangling them.
+fn render_placeholder(placeholder: Placeholder, values: &NameValues) -> OsString {
+    let hash = values.hash.as_deref().unwrap_or_default();
+
+    match placeholder {
+        Placeholder::Stem => values.path.file_stem().unwrap_or_default().to_os_string(),
+        Placeholder::Ext => values.extension.into(),
+        Placeholder::Width => values.dimensions.0.to_string().into(),
+        Placeholder::Height => values.dimensions.1.to_string().into(),
+        Placeholder::Target => values.target.to_string().into(),
+        Placeholder::Hash => hash.into(),
+        Placeholder::Hash8 => hash.chars().take(8).collect::<String>().into(),
+        Placeholder::Dir => relative_dir(values.path).into_os_string(),
+    }
+}
+
+/// Returns the directory of the original file, with anything that
+/// would take us outside the output directory removed -- the root of
+/// an absolute path, and any `..`.
+fn relative_dir(path: &Path) -> PathBuf {
+    path.parent()
+        .map(|p| {
+            p.components()
+                .filter(|c| matches!(c, Component::Normal(_)))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod test_name_template {
+    use super::*;
+
+    fn values(path: &str) -> NameValues<'_> {
+        NameValues {
+            path: Path::new(path),
+            extension: "png",
+            dimensions: (100, 50),
+            target: TargetDimension::MaxWidth(100),
+            hash: Some("0123456789abcdef".to_string()),
+        }
+    }
+
+    fn render(template: &str, path: &str) -> PathBuf {
+        template
+            .parse::<NameTemplate>()
+            .unwrap()
+            .render(&values(path))
+    }
+
+    #[test]
+    fn it_fills_in_the_placeholders() {
+        assert_eq!(
+            render("{stem}_{width}x{height}.{ext}", "photos/cat.jpg"),
+            PathBuf::from("cat_100x50.png")
+        );
+        assert_eq!(
+            render("{hash8}.{ext}", "photos/cat.jpg"),
+            PathBuf::from("01234567.png")
+        );
+        assert_eq!(
+            render("{hash}-{target}", "cat.jpg"),
+            PathBuf::from("0123456789abcdef-w100")
+        );
+    }
+
+    #[test]
+    fn it_mirrors_the_directory_of_the_original() {
+        assert_eq!(
+            render("{dir}/{stem}.{ext}", "photos/2024/cat.jpg"),
+            PathBuf::from("photos/2024/cat.png")
+        );
+        assert_eq!(
+            render("{dir}/{stem}.{ext}", "/srv/photos/cat.jpg"),
+            PathBuf::from("srv/photos/cat.png")
+        );
+        assert_eq!(
+            render("{dir}/{stem}.{ext}", "../photos/cat.jpg"),
+            PathBuf::from("photos/cat.png")
+        );
+        assert_eq!(
+            render("{dir}/{stem}.{ext}", "cat.jpg"),
+            PathBuf::from("cat.png")
+        );
+    }
+
+    #[test]
+    fn it_allows_literal_braces() {
+        assert_eq!(
+            render("{{{stem}}}.{ext}", "cat.jpg"),
+            PathBuf::from("{cat}.png")
+        );
+    }
+
+    #[test]
+    fn it_knows_if_it_needs_a_hash() {
+        assert!("{hash8}.{ext}".parse::<NameTemplate>().unwrap().uses_hash());
+        assert!(!"{stem}.{ext}".parse::<NameTemplate>().unwrap().uses_hash());
+    }
+
+    #[test]
+    fn it_rejects_invalid_templates() {
+        assert_eq!("".parse::<NameTemplate>(), Err(TemplateError::Empty));
+        assert_eq!(
+            "{name}.{ext}".parse::<NameTemplate>(),
+            Err(TemplateError::UnknownPlaceholder("name".to_string()))
+        );
+        assert_eq!(
+            "{stem.{ext}".parse::<NameTemplate>(),
+            Err(TemplateError::UnknownPlaceholder("stem.{ext".to_string()))
+        );
+        assert_eq!(
+            "{stem".parse::<NameTemplate>(),
+            Err(TemplateError::UnclosedBrace)
+        );
+        assert_eq!(
+            "stem}".parse::<NameTemplate>(),
+            Err(TemplateError::UnmatchedBrace)
+        );
+        assert_eq!(
+            "/tmp/{stem}.{ext}".parse::<NameTemplate>(),
+            Err(TemplateError::NotRelative)
+        );
+        assert_eq!(
+            "../{stem}.{ext}".parse::<NameTemplate>(),
+            Err(TemplateError::NotRelative)
+        );
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn it_keeps_names_which_arent_utf8() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        let template: NameTemplate = "{dir}/{stem}_{width}.{ext}".parse().unwrap();
+        let path = Path::new(OsStr::from_bytes(b"caf\xe9/c\xe4t.jpg"));
+
+        assert_eq!(
+            template.render(&NameValues {
+                path,
+                ..values("cat.jpg")
+            }),
+            Path::new(OsStr::from_bytes(b"caf\xe9/c\xe4t_100.png"))
+        );
+    }
+}
@@ -0,0 +1,79 @@
+use crate::animated_encoder::{AnimatedEncoder, FfmpegEncoder};
+use crate::colour::Colour;
+use crate::decode_limits::DecodeLimits;
+use crate::ffmpeg::FfmpegConfig;
+use crate::if_exists::IfExists;
+use crate::name_template::NameTemplate;
+
+/// Extra settings that control how a thumbnail is created.
+///
+/// The defaults match the behaviour of the tool when you don't pass
+/// any of the optional flags.
+#[derive(Debug)]
+pub struct ThumbnailOptions {
+    /// For videos, the timestamp (in seconds) of the frame to use as
+    /// the thumbnail.  If this is `None`, we pick a frame 10% of the
+    /// way through the video.
+    pub video_frame_at: Option<f64>,
+
+    /// For videos, the length (in seconds) of a looping preview clip
+    /// to create alongside the still thumbnail.  If this is `None`,
+    /// we don't create a preview clip.
+    pub video_preview_seconds: Option<f64>,
+
+    /// How to create looping thumbnails, i.e. thumbnails of animated
+    /// GIFs and preview clips of videos.  By default we use ffmpeg.
+    pub animated_encoder: Box<dyn AnimatedEncoder>,
+
+    /// The colour to use if we need to fill in any pixels, e.g. the
+    /// transparent areas of an animated GIF when we turn it into an MP4,
+    /// or when padding a video thumbnail to even dimensions.
+    pub background: Option<Colour>,
+
+    /// If the image has a preview embedded in its EXIF metadata which
+    /// is at least as big as the thumbnail, resize that rather than
+    /// decoding the full image.  This is faster, but the preview may be
+    /// lower quality than the original.
+    pub use_embedded_preview: bool,
+
+    /// How to name thumbnails saved in an output directory.  If this is
+    /// `None`, thumbnails have the same name as the original file, with
+    /// the extension changed if necessary.
+    pub name_template: Option<NameTemplate>,
+
+    /// Save thumbnails in an output directory under a hash of the original
+    /// file and these options, e.g. `ab/cdef….png`, and reuse an existing
+    /// thumbnail if there's already one with the same hash.  This takes
+    /// precedence over `name_template`.
+    pub content_addressed: bool,
+
+    /// What to do if there's already a file where we want to save the
+    /// thumbnail (or a video's preview clip).  Content-addressed
+    /// thumbnails are always reused, whatever this says.
+    pub if_exists: IfExists,
+
+    /// Limits on the images we're willing to decode, so a malicious
+    /// image can't use up all the memory on the machine.
+    pub limits: DecodeLimits,
+
+    /// Where to find ffmpeg, and how long to let it run.  We use this
+    /// to read videos, whichever encoder we're using.
+    pub ffmpeg: FfmpegConfig,
+}
+
+impl Default for ThumbnailOptions {
+    fn default() -> Self {
+        ThumbnailOptions {
+            video_frame_at: None,
+            video_preview_seconds: None,
+            animated_encoder: Box::new(FfmpegEncoder::default()),
+            background: None,
+            use_embedded_preview: false,
+            name_template: None,
+            content_addressed: false,
+            if_exists: IfExists::Overwrite,
+            limits: DecodeLimits::default(),
+            ffmpeg: FfmpegConfig::default(),
+        }
+    }
+}
@@ -0,0 +1,148 @@
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::atomic_file::{register_temp_file, unregister_temp_file};
+
+/// An advisory lock on the path of a thumbnail, held while we decide
+/// whether to create it and while we're creating it.
+///
+/// If two processes want to create a thumbnail at the same path, the
+/// second one waits until the first one is finished.  By then the
+/// thumbnail exists, so the second one can reuse it (with content-addressed
+/// storage or `IfExists::Skip`) rather than racing to write the same file.
+///
+/// The lock is a hidden file next to the thumbnail, e.g. `.cat.jpg.lock`
+/// for `cat.jpg`, which is deleted when the lock is released -- or by
+/// `remove_temp_files`, if we're interrupted.  The lock
+/// is advisory, so it only affects other processes which use it.
+#[derive(Debug)]
+pub struct OutputLock {
+    file: File,
+    lock_path: PathBuf,
+}
+
+impl OutputLock {
+    /// Lock the thumbnail at `thumbnail_path`, waiting if another
+    /// process holds the lock.  The parent directory must already exist.
+    pub fn acquire(thumbnail_path: &Path) -> io::Result<OutputLock> {
+        let lock_path = lock_path_for(thumbnail_path);
+
+        loop {
+            let file = OpenOptions::new()
+                .create(true)
+                .truncate(false)
+                .write(true)
+                .open(&lock_path)?;
+            file.lock()?;
+
+            // The previous holder deletes the lock file when it's done, so
+            // we may have locked a file that's no longer there, while a
+            // third process has created and locked a new one.  If so, we
+            // try again with the new file.
+            if is_same_file(&file, &lock_path) {
+                register_temp_file(&lock_path);
+                return Ok(OutputLock { file, lock_path });
+            }
+        }
+    }
+}
+
+impl Drop for OutputLock {
+    fn drop(&mut self) {
+        // We delete the file before we unlock it, so anybody waiting
+        // for it notices that it's gone.
+        let _ = fs::remove_file(&self.lock_path);
+        unregister_temp_file(&self.lock_path);
+        let _ = self.file.unlock();
+    }
+}
+
+/// Returns the path of the lock file for a thumbnail.
+fn lock_path_for(thumbnail_path: &Path) -> PathBuf {
+    let mut name = OsString::from(".");
+    name.push(thumbnail_path.file_name().unwrap_or_default());
+    name.push(".lock");
+
+    thumbnail_path.with_file_name(name)
+}
+
+/// Returns true if `path` is the file we have open as `file`.
+#[cfg(unix)]
+fn is_same_file(file: &File, path: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+
+    match (file.metadata(), fs::metadata(path)) {
+        (Ok(a), Ok(b)) => a.dev() == b.dev() && a.ino() == b.ino(),
+        _ => false,
+    }
+}
+
+/// Returns true if `path` is the file we have open as `file`.
+///
+/// Other platforms don't let us delete a file while somebody has it
+/// open, so the file we locked is always still there.
+#[cfg(not(unix))]
+fn is_same_file(_: &File, path: &Path) -> bool {
+    path.exists()
+}
+
+#[cfg(test)]
+mod test_output_lock {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    use super::*;
+    use crate::test_utils::test_dir;
+
+    #[test]
+    fn it_waits_for_another_lock_on_the_same_path() {
+        let dir = test_dir();
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("cat.jpg");
+
+        let first = OutputLock::acquire(&path).unwrap();
+        let acquired = Arc::new(AtomicBool::new(false));
+
+        let second = {
+            let path = path.clone();
+            let acquired = Arc::clone(&acquired);
+            thread::spawn(move || {
+                let _lock = OutputLock::acquire(&path).unwrap();
+                acquired.store(true, Ordering::SeqCst);
+            })
+        };
+
+        thread::sleep(Duration::from_millis(200));
+        assert!(!acquired.load(Ordering::SeqCst));
+
+        drop(first);
+        second.join().unwrap();
+        assert!(acquired.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn it_doesnt_wait_for_a_lock_on_a_different_path() {
+        let dir = test_dir();
+        fs::create_dir_all(&dir).unwrap();
+
+        let _cat = OutputLock::acquire(&dir.join("cat.jpg")).unwrap();
+        let _dog = OutputLock::acquire(&dir.join("dog.jpg")).unwrap();
+    }
+
+    #[test]
+    fn it_deletes_the_lock_file_when_released() {
+        let dir = test_dir();
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("cat.jpg");
+
+        let lock = OutputLock::acquire(&path).unwrap();
+        assert!(dir.join(".cat.jpg.lock").exists());
+
+        drop(lock);
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 0);
+    }
+}
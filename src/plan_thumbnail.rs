@@ -0,0 +1,374 @@
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use image::ImageFormat;
+use serde::Serialize;
+
+use crate::create_thumbnail::{
+    existing_thumbnail, image_thumbnail_path, output_format, static_format, video_thumbnail_path,
+};
+use crate::create_video_thumbnail::{is_video, probe_video};
+use crate::errors::ThumbnailError;
+use crate::get_thumbnail_dimensions::{
+    calculate_dimensions, get_thumbnail_dimensions, TargetDimension,
+};
+use crate::if_exists::resolve_output_paths;
+use crate::is_same_path::is_same_path;
+use crate::options::ThumbnailOptions;
+use crate::source_image::SourceImage;
+use crate::validate_out_dir::validate_out_dir;
+
+/// What would happen if we created a thumbnail, without creating it.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct ThumbnailPlan {
+    /// Path to the original image
+    #[serde(serialize_with = "crate::serde_path::serialize")]
+    pub source: PathBuf,
+
+    /// Where the thumbnail would be saved
+    #[serde(serialize_with = "crate::serde_path::serialize")]
+    pub path: PathBuf,
+
+    /// Where the preview clip would be saved, if we'd create one
+    #[serde(
+        serialize_with = "crate::serde_path::option::serialize",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub preview_path: Option<PathBuf>,
+
+    /// Width of the original image, in pixels
+    pub source_width: u32,
+
+    /// Height of the original image, in pixels
+    pub source_height: u32,
+
+    /// Width of the thumbnail, in pixels
+    pub width: u32,
+
+    /// Height of the thumbnail, in pixels
+    pub height: u32,
+
+    /// Whether the original is animated, i.e. a video or animated GIF
+    pub is_animated: bool,
+
+    /// What we'd do with the thumbnail
+    pub action: PlannedAction,
+}
+
+/// What we'd do with a thumbnail.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PlannedAction {
+    /// Create a new file
+    Create,
+
+    /// Replace an existing file
+    Overwrite,
+
+    /// Leave an existing file alone, because of `IfExists::Skip`
+    Skip,
+
+    /// Reuse an existing content-addressed thumbnail
+    Reuse,
+}
+
+impl fmt::Display for PlannedAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PlannedAction::Create => write!(f, "create"),
+            PlannedAction::Overwrite => write!(f, "overwrite"),
+            PlannedAction::Skip => write!(f, "skip"),
+            PlannedAction::Reuse => write!(f, "reuse"),
+        }
+    }
+}
+
+impl fmt::Display for ThumbnailPlan {
+    /// Describe the plan on one line, e.g.
+    /// `cat.jpg (400x300) -> out/cat.jpg (100x75): create`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} ({}x{}{}) -> {} ({}x{})",
+            self.source.display(),
+            self.source_width,
+            self.source_height,
+            if self.is_animated { ", animated" } else { "" },
+            self.path.display(),
+            self.width,
+            self.height,
+        )?;
+
+        if let Some(preview_path) = &self.preview_path {
+            write!(f, ", preview {}", preview_path.display())?;
+        }
+
+        write!(f, ": {}", self.action)
+    }
+}
+
+/// Work out what [`create_thumbnail`](crate::create_thumbnail) would do,
+/// without doing it.
+///
+/// We read the headers of the image to get its dimensions, and work out
+/// where the thumbnail would be saved and whether we'd replace an existing
+/// file, but we don't decode the image or write anything.  For videos we
+/// run ffprobe to read the dimensions, but we never run ffmpeg.
+///
+/// If creating the thumbnail would fail because of `IfExists::Error`,
+/// because it would overwrite the original, or because we can't save
+/// anything in `out_dir`, so does this.
+pub fn plan_thumbnail(
+    path: &Path,
+    out_dir: &Path,
+    target: TargetDimension,
+    options: &ThumbnailOptions,
+) -> Result<ThumbnailPlan, ThumbnailError> {
+    validate_out_dir(out_dir)?;
+
+    if is_video(path) {
+        let info = probe_video(path, &options.ffmpeg)?;
+        let (thumbnail_path, (width, height)) =
+            video_thumbnail_path(path, out_dir, &info, target, options)?;
+
+        return plan_output(
+            path,
+            &thumbnail_path,
+            (info.width, info.height),
+            (width, height),
+            true,
+            options,
+        );
+    }
+
+    let source = SourceImage::open(path, &options.limits)?;
+    let (thumbnail_path, dimensions) =
+        image_thumbnail_path(path, out_dir, &source, target, options)?;
+
+    plan_output(
+        path,
+        &thumbnail_path,
+        source.dimensions,
+        dimensions,
+        source.is_animated,
+        options,
+    )
+}
+
+/// Work out what [`create_thumbnail_at`](crate::create_thumbnail_at) would
+/// do, without doing it.  See [`plan_thumbnail`].
+pub fn plan_thumbnail_at(
+    path: &Path,
+    thumbnail_path: &Path,
+    target: TargetDimension,
+    format: Option<ImageFormat>,
+    options: &ThumbnailOptions,
+) -> Result<ThumbnailPlan, ThumbnailError> {
+    let is_stdin = path == Path::new("-");
+
+    validate_out_dir(thumbnail_path.parent().unwrap_or(Path::new("")))?;
+
+    if !is_stdin && is_video(path) {
+        let info = probe_video(path, &options.ffmpeg)?;
+        static_format(thumbnail_path, format)?;
+
+        return plan_output(
+            path,
+            thumbnail_path,
+            (info.width, info.height),
+            calculate_dimensions((info.width, info.height), target),
+            true,
+            options,
+        );
+    }
+
+    let source = if is_stdin {
+        SourceImage::from_reader(io::stdin().lock(), &options.limits)?
+    } else {
+        SourceImage::open(path, &options.limits)?
+    };
+    output_format(&source, thumbnail_path, format, options)?;
+
+    let (width, height) = get_thumbnail_dimensions(&source, target);
+    let dimensions = if source.is_animated {
+        options.animated_encoder.output_dimensions(width, height)
+    } else {
+        (width, height)
+    };
+
+    plan_output(
+        path,
+        thumbnail_path,
+        source.dimensions,
+        dimensions,
+        source.is_animated,
+        options,
+    )
+}
+
+/// Decide what we'd do with the thumbnail at `thumbnail_path`, in the
+/// same way as `create_thumbnail`.
+fn plan_output(
+    path: &Path,
+    thumbnail_path: &Path,
+    (source_width, source_height): (u32, u32),
+    (width, height): (u32, u32),
+    is_animated: bool,
+    options: &ThumbnailOptions,
+) -> Result<ThumbnailPlan, ThumbnailError> {
+    if path != Path::new("-") && is_same_path(path, thumbnail_path) {
+        return Err(ThumbnailError::SameInputOutputPath(
+            thumbnail_path.to_path_buf(),
+        ));
+    }
+
+    // Only videos get a preview clip.
+    let preview_extension = options
+        .video_preview_seconds
+        .filter(|_| is_video(path))
+        .map(|_| options.animated_encoder.extension());
+
+    // The still thumbnail and the preview clip are renamed together, so
+    // we resolve both paths, even though we only report on the still.
+    let mut paths = vec![thumbnail_path.to_path_buf()];
+    paths.extend(preview_extension.map(|ext| thumbnail_path.with_extension(ext)));
+
+    let (thumbnail_path, action) = if existing_thumbnail(
+        thumbnail_path,
+        (width, height),
+        preview_extension,
+        options,
+    )
+    .is_some()
+    {
+        (thumbnail_path.to_path_buf(), PlannedAction::Reuse)
+    } else {
+        match resolve_output_paths(&paths, options.if_exists)?.remove(0) {
+            None => (thumbnail_path.to_path_buf(), PlannedAction::Skip),
+            Some(p) if p.exists() => (p, PlannedAction::Overwrite),
+            Some(p) => (p, PlannedAction::Create),
+        }
+    };
+
+    Ok(ThumbnailPlan {
+        source: path.to_path_buf(),
+        preview_path: preview_extension.map(|ext| thumbnail_path.with_extension(ext)),
+        path: thumbnail_path,
+        source_width,
+        source_height,
+        width,
+        height,
+        is_animated,
+        action,
+    })
+}
+
+#[cfg(test)]
+mod test_plan_thumbnail {
+    use std::fs;
+
+    use super::*;
+    use crate::if_exists::IfExists;
+    use crate::test_utils::test_dir;
+
+    #[test]
+    fn it_plans_a_thumbnail_without_creating_it() {
+        let out_dir = test_dir();
+        let target = TargetDimension::MaxWidth(50);
+
+        let plan = plan_thumbnail(
+            Path::new("src/tests/red.png"),
+            &out_dir,
+            target,
+            &ThumbnailOptions::default(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            plan,
+            ThumbnailPlan {
+                source: PathBuf::from("src/tests/red.png"),
+                path: out_dir.join("red.png"),
+                preview_path: None,
+                source_width: 100,
+                source_height: 200,
+                width: 50,
+                height: 100,
+                is_animated: false,
+                action: PlannedAction::Create,
+            }
+        );
+        assert!(!out_dir.exists());
+    }
+
+    #[test]
+    fn it_plans_an_animated_thumbnail() {
+        let plan = plan_thumbnail(
+            Path::new("src/tests/animated_squares.gif"),
+            &test_dir(),
+            TargetDimension::MaxWidth(16),
+            &ThumbnailOptions::default(),
+        )
+        .unwrap();
+
+        assert!(plan.is_animated);
+        assert_eq!(plan.path.extension().unwrap(), "mp4");
+    }
+
+    #[test]
+    fn it_plans_what_to_do_with_an_existing_file() {
+        let out_dir = test_dir();
+        fs::create_dir_all(&out_dir).unwrap();
+        fs::write(out_dir.join("red.png"), "").unwrap();
+
+        let plan = |if_exists| {
+            let options = ThumbnailOptions {
+                if_exists,
+                ..Default::default()
+            };
+
+            plan_thumbnail(
+                Path::new("src/tests/red.png"),
+                &out_dir,
+                TargetDimension::MaxWidth(50),
+                &options,
+            )
+        };
+
+        let overwrite = plan(IfExists::Overwrite).unwrap();
+        assert_eq!(overwrite.action, PlannedAction::Overwrite);
+
+        let skip = plan(IfExists::Skip).unwrap();
+        assert_eq!(skip.action, PlannedAction::Skip);
+
+        let rename = plan(IfExists::Rename).unwrap();
+        assert_eq!(rename.action, PlannedAction::Create);
+        assert_eq!(rename.path, out_dir.join("red-1.png"));
+
+        assert!(matches!(
+            plan(IfExists::Error),
+            Err(ThumbnailError::OutputExists(_))
+        ));
+    }
+
+    #[test]
+    fn it_describes_the_plan() {
+        let plan = ThumbnailPlan {
+            source: PathBuf::from("cat.gif"),
+            path: PathBuf::from("out/cat.mp4"),
+            preview_path: None,
+            source_width: 400,
+            source_height: 300,
+            width: 100,
+            height: 76,
+            is_animated: true,
+            action: PlannedAction::Overwrite,
+        };
+
+        assert_eq!(
+            plan.to_string(),
+            "cat.gif (400x300, animated) -> out/cat.mp4 (100x76): overwrite"
+        );
+    }
+}
@@ -0,0 +1,175 @@
+use std::fmt;
+use std::io::{self, Cursor};
+use std::path::Path;
+
+use image::{ImageDecoder, ImageFormat, ImageReader};
+use serde::Serialize;
+
+use crate::create_thumbnail::format_name;
+use crate::decode_limits::DecodeLimits;
+use crate::errors::ThumbnailError;
+use crate::gif_animation_length::gif_animation_length;
+use crate::source_image::SourceImage;
+
+/// Information about an image, from its headers.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct ImageInfo {
+    /// The format of the image, based on its contents, e.g. `PNG`
+    pub format: String,
+
+    /// Width of the image, in pixels, after the EXIF orientation has
+    /// been applied
+    pub width: u32,
+
+    /// Height of the image, in pixels, after the EXIF orientation has
+    /// been applied
+    pub height: u32,
+
+    /// How the pixels are stored in the file, e.g. `Rgb8` or `La16`
+    pub colour_type: String,
+
+    /// The number of bits in each channel of a pixel
+    pub bit_depth: u16,
+
+    /// The EXIF orientation of the image, from 1 (upright) to 8
+    pub orientation: u8,
+
+    /// Whether the image has an embedded ICC colour profile
+    pub has_icc_profile: bool,
+
+    /// Whether this is an animated GIF
+    pub is_animated: bool,
+
+    /// The number of frames in the image
+    pub frames: u64,
+
+    /// How long the animation takes to play once, in seconds, if this
+    /// is a GIF
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub animation_duration: Option<f64>,
+}
+
+impl fmt::Display for ImageInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "format:      {}", self.format)?;
+        writeln!(f, "dimensions:  {}x{}", self.width, self.height)?;
+        writeln!(f, "colour type: {}", self.colour_type)?;
+        writeln!(f, "bit depth:   {}", self.bit_depth)?;
+        writeln!(f, "orientation: {}", self.orientation)?;
+        writeln!(
+            f,
+            "ICC profile: {}",
+            if self.has_icc_profile { "yes" } else { "no" }
+        )?;
+        writeln!(
+            f,
+            "animated:    {}",
+            if self.is_animated { "yes" } else { "no" }
+        )?;
+        write!(f, "frames:      {}", self.frames)?;
+
+        if let Some(duration) = self.animation_duration {
+            write!(f, "\nduration:    {}s", duration)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Read the headers of an image, and describe it.
+///
+/// If `path` is `-`, we read the image from stdin.  Like creating a
+/// thumbnail, this only reads the headers and doesn't decode any pixels,
+/// and it returns an error if the image exceeds any of the `limits`.
+pub fn probe_image(path: &Path, limits: &DecodeLimits) -> Result<ImageInfo, ThumbnailError> {
+    let source = if path == Path::new("-") {
+        SourceImage::from_reader(io::stdin().lock(), limits)?
+    } else {
+        SourceImage::open(path, limits)?
+    };
+
+    let to_thumbnail_error = |e| ThumbnailError::image(source.path(), e);
+
+    let mut decoder = ImageReader::with_format(Cursor::new(source.bytes()), source.format)
+        .into_decoder()
+        .map_err(to_thumbnail_error)?;
+    let colour_type = decoder.original_color_type();
+    let has_icc_profile = decoder.icc_profile().map_err(to_thumbnail_error)?.is_some();
+
+    let (frames, animation_duration) = if source.format == ImageFormat::Gif {
+        let (frames, duration) =
+            gif_animation_length(Cursor::new(source.bytes()), source.path(), limits)?;
+        (frames, Some(duration.as_secs_f64()))
+    } else {
+        (1, None)
+    };
+
+    Ok(ImageInfo {
+        format: format_name(source.format),
+        width: source.dimensions.0,
+        height: source.dimensions.1,
+        colour_type: format!("{:?}", colour_type),
+        bit_depth: colour_type.bits_per_pixel() / colour_type.channel_count().max(1) as u16,
+        orientation: source.orientation.to_exif(),
+        has_icc_profile,
+        is_animated: source.is_animated,
+        frames,
+        animation_duration,
+    })
+}
+
+#[cfg(test)]
+mod test_probe_image {
+    use super::*;
+
+    fn probe(path: &str) -> ImageInfo {
+        probe_image(Path::new(path), &DecodeLimits::default()).unwrap()
+    }
+
+    #[test]
+    fn it_probes_a_png() {
+        let info = probe("src/tests/red.png");
+
+        assert_eq!(info.format, "PNG");
+        assert_eq!((info.width, info.height), (100, 200));
+        assert_eq!(info.orientation, 1);
+        assert!(!info.is_animated);
+        assert_eq!(info.frames, 1);
+        assert_eq!(info.animation_duration, None);
+    }
+
+    #[test]
+    fn it_probes_an_animated_gif() {
+        let info = probe("src/tests/animated_squares.gif");
+
+        assert_eq!(info.format, "GIF");
+        assert!(info.is_animated);
+        assert!(info.frames > 1);
+        assert!(info.animation_duration.unwrap() > 0.0);
+    }
+
+    #[test]
+    fn it_probes_a_static_gif() {
+        let info = probe("src/tests/static.gif");
+
+        assert!(!info.is_animated);
+        assert_eq!(info.frames, 1);
+    }
+
+    #[test]
+    fn it_reports_the_exif_orientation() {
+        let info = probe("src/tests/Landscape_5.jpg");
+
+        assert_eq!(info.format, "JPEG");
+        assert_eq!(info.orientation, 5);
+        assert_eq!(info.colour_type, "Rgb8");
+        assert_eq!(info.bit_depth, 8);
+    }
+
+    #[test]
+    fn it_fails_if_the_file_isnt_an_image() {
+        let result = probe_image(Path::new("README.md"), &DecodeLimits::default());
+
+        assert!(result.is_err());
+    }
+}
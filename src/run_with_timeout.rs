@@ -0,0 +1,120 @@
+use std::io::{self, Read, Write};
+use std::process::{Child, Command, Output, Stdio};
+use std::thread;
+use std::time::Duration;
+
+use wait_timeout::ChildExt;
+
+/// Run a command, killing it if it doesn't finish within `timeout`.
+///
+/// This returns `None` if we had to kill the command.
+///
+/// If there's any `input`, we write it to the command's stdin; otherwise
+/// stdin is closed.  We write stdin and read stdout and stderr on
+/// background threads -- if we waited for the process to exit first,
+/// it could block forever trying to write to a full pipe.
+pub fn run_with_timeout(
+    mut cmd: Command,
+    input: Option<&[u8]>,
+    timeout: Duration,
+) -> io::Result<Option<Output>> {
+    let stdin = if input.is_some() {
+        Stdio::piped()
+    } else {
+        Stdio::null()
+    };
+
+    let mut child = cmd
+        .stdin(stdin)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    let stdout = read_in_background(child.stdout.take());
+    let stderr = read_in_background(child.stderr.take());
+
+    thread::scope(|scope| {
+        if let (Some(mut pipe), Some(input)) = (child.stdin.take(), input) {
+            // If the command exits without reading all of its input, this
+            // write fails with a broken pipe.  That's fine -- we'll find
+            // out what went wrong from its exit status.  Dropping the pipe
+            // when we're done tells the command there's no more input.
+            scope.spawn(move || {
+                let _ = pipe.write_all(input);
+            });
+        }
+
+        let status = match child.wait_timeout(timeout)? {
+            Some(status) => status,
+            None => {
+                kill(&mut child)?;
+                return Ok(None);
+            }
+        };
+
+        Ok(Some(Output {
+            status,
+            stdout: stdout.join().unwrap_or_default(),
+            stderr: stderr.join().unwrap_or_default(),
+        }))
+    })
+}
+
+/// Read everything from a pipe on a background thread.
+fn read_in_background<R: Read + Send + 'static>(pipe: Option<R>) -> thread::JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut buf = Vec::new();
+        if let Some(mut pipe) = pipe {
+            let _ = pipe.read_to_end(&mut buf);
+        }
+        buf
+    })
+}
+
+/// Kill a child process, and wait for it to exit so it doesn't
+/// become a zombie.
+fn kill(child: &mut Child) -> io::Result<()> {
+    child.kill()?;
+    child.wait()?;
+    Ok(())
+}
+
+#[cfg(all(test, unix))]
+mod test_run_with_timeout {
+    use super::*;
+
+    #[test]
+    fn it_returns_the_output_of_the_command() {
+        let mut cmd = Command::new("sh");
+        cmd.args(["-c", "echo hello; echo world >&2; exit 3"]);
+
+        let output = run_with_timeout(cmd, None, Duration::from_secs(10))
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(output.stdout, b"hello\n");
+        assert_eq!(output.stderr, b"world\n");
+        assert_eq!(output.status.code(), Some(3));
+    }
+
+    #[test]
+    fn it_kills_a_command_which_takes_too_long() {
+        let mut cmd = Command::new("sleep");
+        cmd.arg("10");
+
+        let output = run_with_timeout(cmd, None, Duration::from_millis(100)).unwrap();
+
+        assert!(output.is_none());
+    }
+
+    #[test]
+    fn it_passes_input_to_the_command() {
+        let cmd = Command::new("cat");
+
+        let output = run_with_timeout(cmd, Some(b"hello world"), Duration::from_secs(10))
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(output.stdout, b"hello world");
+    }
+}
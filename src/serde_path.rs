@@ -0,0 +1,124 @@
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// Serialize a path without losing anything, for `#[serde(with = ...)]`.
+///
+/// Serde refuses to serialize a path which isn't valid UTF-8, but that's
+/// a perfectly good file name on Linux.  We write the path as a string
+/// if we can, and as a list of its raw bytes if we can't -- which means
+/// the worker process can tell us about a thumbnail with any name.
+pub fn serialize<S: Serializer>(path: &Path, serializer: S) -> Result<S::Ok, S::Error> {
+    match path.to_str() {
+        Some(s) => serializer.serialize_str(s),
+        None => RawPath::from(path).serialize(serializer),
+    }
+}
+
+/// Deserialize a path written by [`serialize`].
+pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<PathBuf, D::Error> {
+    RawPath::deserialize(deserializer).map(PathBuf::from)
+}
+
+/// The same as [`serialize`] and [`deserialize`], for optional paths.
+pub mod option {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(
+        path: &Option<PathBuf>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match path {
+            Some(path) => super::serialize(path, serializer),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<PathBuf>, D::Error> {
+        Option::<RawPath>::deserialize(deserializer).map(|p| p.map(PathBuf::from))
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(untagged)]
+enum RawPath {
+    Utf8(String),
+    Bytes(Vec<u8>),
+}
+
+impl From<&Path> for RawPath {
+    #[cfg(unix)]
+    fn from(path: &Path) -> RawPath {
+        use std::os::unix::ffi::OsStrExt;
+
+        RawPath::Bytes(path.as_os_str().as_bytes().to_vec())
+    }
+
+    /// Other platforms don't have a way to get at the raw bytes, but
+    /// paths on Windows are almost always valid Unicode anyway.
+    #[cfg(not(unix))]
+    fn from(path: &Path) -> RawPath {
+        RawPath::Utf8(path.to_string_lossy().into_owned())
+    }
+}
+
+impl From<RawPath> for PathBuf {
+    fn from(raw: RawPath) -> PathBuf {
+        match raw {
+            RawPath::Utf8(s) => PathBuf::from(s),
+
+            #[cfg(unix)]
+            RawPath::Bytes(bytes) => {
+                use std::ffi::OsString;
+                use std::os::unix::ffi::OsStringExt;
+
+                PathBuf::from(OsString::from_vec(bytes))
+            }
+
+            #[cfg(not(unix))]
+            RawPath::Bytes(bytes) => PathBuf::from(String::from_utf8_lossy(&bytes).into_owned()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_serde_path {
+    use super::*;
+
+    #[derive(Debug, Deserialize, PartialEq, Serialize)]
+    struct File {
+        #[serde(with = "crate::serde_path")]
+        path: PathBuf,
+    }
+
+    #[test]
+    fn it_writes_a_utf8_path_as_a_string() {
+        let file = File {
+            path: PathBuf::from("cät.jpg"),
+        };
+
+        let json = serde_json::to_string(&file).unwrap();
+
+        assert_eq!(json, r#"{"path":"cät.jpg"}"#);
+        assert_eq!(serde_json::from_str::<File>(&json).unwrap(), file);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn it_writes_a_non_utf8_path_as_bytes() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        // This is `cät.jpg` in Latin-1.
+        let file = File {
+            path: PathBuf::from(OsStr::from_bytes(b"c\xe4t.jpg")),
+        };
+
+        let json = serde_json::to_string(&file).unwrap();
+
+        assert_eq!(json, r#"{"path":[99,228,116,46,106,112,103]}"#);
+        assert_eq!(serde_json::from_str::<File>(&json).unwrap(), file);
+    }
+}
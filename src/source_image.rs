@@ -0,0 +1,558 @@
+use std::fs::File;
+use std::io::{Cursor, Read};
+use std::path::{Path, PathBuf};
+
+use image::metadata::Orientation;
+use image::{DynamicImage, GenericImageView, ImageDecoder, ImageFormat, ImageReader};
+
+use crate::decode_limits::DecodeLimits;
+use crate::decode_scaled_jpeg::decode_scaled_jpeg;
+use crate::errors::ThumbnailError;
+use crate::extract_embedded_preview::extract_embedded_preview;
+use crate::gif_animation_length::gif_animation_length;
+use crate::is_animated_gif::is_animated_gif;
+
+/// An image we want to thumbnail.
+///
+/// When we create a `SourceImage` we read the file once, and look at the
+/// headers to get the format, dimensions and orientation -- but we don't
+/// decode any pixels until you call `decode()`.  Decoding a large image
+/// is expensive, so we want to do it exactly once.
+#[derive(Debug)]
+pub struct SourceImage {
+    bytes: Vec<u8>,
+
+    /// The format of the image, based on its contents rather than
+    /// its extension
+    pub format: ImageFormat,
+
+    /// The width and height of the image, after the EXIF orientation
+    /// has been applied
+    pub dimensions: (u32, u32),
+
+    /// The EXIF orientation of the image
+    pub orientation: Orientation,
+
+    /// Whether this is an animated GIF
+    pub is_animated: bool,
+
+    /// The limits we apply when decoding the image
+    limits: DecodeLimits,
+
+    /// Where we read the image from, so we can include it in errors,
+    /// or empty if it came from a stream
+    path: PathBuf,
+}
+
+impl SourceImage {
+    /// Read an image from disk, and inspect its headers.
+    ///
+    /// This returns an error if the image exceeds any of the `limits`.
+    pub fn open(path: &Path, limits: &DecodeLimits) -> Result<SourceImage, ThumbnailError> {
+        let to_thumbnail_error = |e| ThumbnailError::io(path, e);
+
+        let mut file = File::open(path).map_err(to_thumbnail_error)?;
+        let len = file.metadata().map_err(to_thumbnail_error)?.len();
+        limits
+            .check_input_bytes(len)
+            .map_err(|limit| ThumbnailError::limit(path, limit))?;
+
+        let mut bytes = Vec::new();
+        file.read_to_end(&mut bytes).map_err(to_thumbnail_error)?;
+
+        // We decide the format by looking at the contents of the file,
+        // not its extension, so mislabelled files still work.  We only
+        // use the extension for formats without a recognisable signature.
+        let format = match image::guess_format(&bytes) {
+            Ok(format) => format,
+            Err(e) => ImageFormat::from_path(path).map_err(|_| ThumbnailError::image(path, e))?,
+        };
+
+        SourceImage::from_bytes(bytes, format, limits, path)
+    }
+
+    /// Read an image from a stream, e.g. stdin, and inspect its headers.
+    ///
+    /// There's no file name to fall back on, so the format has to be
+    /// recognisable from the contents of the image, and errors have an
+    /// empty path.
+    pub fn from_reader(
+        mut reader: impl Read,
+        limits: &DecodeLimits,
+    ) -> Result<SourceImage, ThumbnailError> {
+        let path = Path::new("");
+
+        // We don't know how long the stream is until we've read it, so
+        // we stop one byte past the limit -- enough to know it's too big.
+        let mut bytes = Vec::new();
+        match limits.max_input_bytes {
+            Some(max) => reader.take(max.saturating_add(1)).read_to_end(&mut bytes),
+            None => reader.read_to_end(&mut bytes),
+        }
+        .map_err(|e| ThumbnailError::io(path, e))?;
+        limits
+            .check_input_bytes(bytes.len() as u64)
+            .map_err(|limit| ThumbnailError::limit(path, limit))?;
+
+        let format = image::guess_format(&bytes).map_err(|e| ThumbnailError::image(path, e))?;
+
+        SourceImage::from_bytes(bytes, format, limits, path)
+    }
+
+    /// Inspect the headers of an image which is already in memory, and
+    /// which we read from `path`.
+    fn from_bytes(
+        bytes: Vec<u8>,
+        format: ImageFormat,
+        limits: &DecodeLimits,
+        path: &Path,
+    ) -> Result<SourceImage, ThumbnailError> {
+        let to_thumbnail_error = |e| ThumbnailError::image(path, e);
+        let to_limit_error = |limit| ThumbnailError::limit(path, limit);
+
+        // Creating a decoder only reads the headers, not the pixel data.
+        let (orientation, dimensions) = {
+            let mut decoder = ImageReader::with_format(Cursor::new(&bytes), format)
+                .into_decoder()
+                .map_err(to_thumbnail_error)?;
+            decoder
+                .set_limits(limits.image_limits())
+                .map_err(to_thumbnail_error)?;
+            limits.check_decoder(&decoder).map_err(to_limit_error)?;
+            let orientation = decoder.orientation().map_err(to_thumbnail_error)?;
+            (
+                orientation,
+                oriented_dimensions(decoder.dimensions(), orientation),
+            )
+        };
+
+        let is_animated = format == ImageFormat::Gif && is_animated_gif(Cursor::new(&bytes));
+
+        // Counting the frames in a GIF means reading the whole file, so we
+        // only do it if there's a limit to check.
+        if is_animated && limits.limits_animation() {
+            gif_animation_length(Cursor::new(&bytes), path, limits)?;
+        }
+
+        Ok(SourceImage {
+            bytes,
+            format,
+            dimensions,
+            orientation,
+            is_animated,
+            limits: limits.clone(),
+            path: path.to_path_buf(),
+        })
+    }
+
+    /// Where we read the image from, or an empty path if it came from
+    /// a stream.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The original, undecoded contents of the image.
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Decode the pixel data of the image, and apply the EXIF orientation.
+    pub fn decode(&self) -> Result<DynamicImage, ThumbnailError> {
+        let mut reader = ImageReader::with_format(Cursor::new(&self.bytes), self.format);
+        reader.limits(self.limits.image_limits());
+
+        let mut img = reader
+            .into_decoder()
+            .and_then(DynamicImage::from_decoder)
+            .map_err(|e| ThumbnailError::image(&self.path, e))?;
+        img.apply_orientation(self.orientation);
+
+        Ok(img)
+    }
+
+    /// Decode the image for a thumbnail which is `width`×`height`.
+    ///
+    /// For JPEGs, this may decode the image at a reduced size, which is
+    /// much faster than decoding it at full size -- but it's always at
+    /// least as big as the thumbnail.  For every other format, this is
+    /// the same as `decode()`.
+    pub fn decode_for_thumbnail(
+        &self,
+        width: u32,
+        height: u32,
+    ) -> Result<DynamicImage, ThumbnailError> {
+        if self.format == ImageFormat::Jpeg {
+            // The decoder works on the image as stored, so we need to undo
+            // any rotation before we tell it how big the thumbnail is.
+            let (stored_width, stored_height) =
+                oriented_dimensions((width, height), self.orientation);
+
+            if let Some(mut img) =
+                decode_scaled_jpeg(&self.bytes, stored_width, stored_height, &self.limits).map_err(
+                    |limit| ThumbnailError::LimitsExceeded {
+                        path: self.path.clone(),
+                        limit,
+                    },
+                )?
+            {
+                img.apply_orientation(self.orientation);
+                return Ok(img);
+            }
+        }
+
+        self.decode()
+    }
+
+    /// Decode the preview image embedded in the EXIF metadata, if it's
+    /// big enough to create a `width`×`height` thumbnail.
+    ///
+    /// The preview is checked against the same limits as the full image.
+    ///
+    /// We also check the preview has the same aspect ratio as the full
+    /// image -- some cameras add black bars to fit a fixed preview size,
+    /// and we don't want those in the thumbnail.
+    pub fn decode_embedded_preview(&self, width: u32, height: u32) -> Option<DynamicImage> {
+        let mut preview = extract_embedded_preview(&self.bytes, &self.limits)?;
+        preview.apply_orientation(self.orientation);
+
+        let (preview_width, preview_height) = preview.dimensions();
+        if preview_width < width || preview_height < height {
+            return None;
+        }
+
+        let aspect_ratio = |(w, h): (u32, u32)| (w as f64) / (h as f64);
+        let difference = aspect_ratio(preview.dimensions()) / aspect_ratio(self.dimensions);
+        if !(0.99..=1.01).contains(&difference) {
+            return None;
+        }
+
+        Some(preview)
+    }
+}
+
+/// Returns the dimensions of an image after it's been rotated/flipped
+/// according to its EXIF orientation.
+///
+/// If the image is rotated by 90 or 270 degrees, the width and height
+/// get swapped.
+fn oriented_dimensions((width, height): (u32, u32), orientation: Orientation) -> (u32, u32) {
+    match orientation {
+        Orientation::Rotate90
+        | Orientation::Rotate270
+        | Orientation::Rotate90FlipH
+        | Orientation::Rotate270FlipH => (height, width),
+        _ => (width, height),
+    }
+}
+
+#[cfg(test)]
+mod test_source_image {
+    use std::path::PathBuf;
+
+    use image::imageops::FilterType;
+
+    use std::fs;
+
+    use super::*;
+    use crate::decode_limits::LimitExceeded;
+    use crate::test_utils::test_dir;
+
+    #[test]
+    fn it_reads_the_dimensions_of_an_image() {
+        let source = SourceImage::open(
+            &PathBuf::from("src/tests/red.png"),
+            &DecodeLimits::default(),
+        )
+        .unwrap();
+
+        assert_eq!(source.format, ImageFormat::Png);
+        assert_eq!(source.dimensions, (100, 200));
+        assert!(!source.is_animated);
+    }
+
+    #[test]
+    fn it_applies_the_exif_orientation_to_the_dimensions() {
+        let source = SourceImage::open(
+            &PathBuf::from("src/tests/Landscape_5.jpg"),
+            &DecodeLimits::default(),
+        )
+        .unwrap();
+
+        assert_eq!(source.orientation, Orientation::Rotate90FlipH);
+        assert_eq!(source.dimensions, (1800, 1200));
+        assert_eq!(source.decode().unwrap().dimensions(), (1800, 1200));
+    }
+
+    #[test]
+    fn it_detects_an_animated_gif() {
+        let source = SourceImage::open(
+            &PathBuf::from("src/tests/animated_squares.gif"),
+            &DecodeLimits::default(),
+        )
+        .unwrap();
+
+        assert!(source.is_animated);
+    }
+
+    #[test]
+    fn a_static_gif_is_not_animated() {
+        let source = SourceImage::open(
+            &PathBuf::from("src/tests/static.gif"),
+            &DecodeLimits::default(),
+        )
+        .unwrap();
+
+        assert!(!source.is_animated);
+    }
+
+    #[test]
+    fn errors_if_image_does_not_exist() {
+        let result = SourceImage::open(
+            &PathBuf::from("src/tests/doesnotexist.png"),
+            &DecodeLimits::default(),
+        );
+
+        assert!(matches!(
+            result,
+            Err(ThumbnailError::NotFound { path, .. })
+                if path == Path::new("src/tests/doesnotexist.png")
+        ));
+    }
+
+    #[test]
+    fn errors_include_the_path_to_the_image() {
+        let limits = DecodeLimits {
+            max_pixels: Some(10),
+            ..Default::default()
+        };
+
+        let err = SourceImage::open(&PathBuf::from("src/tests/red.png"), &limits).unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            "Image src/tests/red.png is too big to thumbnail: it has 20000 pixels, but the limit is 10"
+        );
+        assert_eq!(err.exit_code(), 7);
+    }
+
+    #[test]
+    fn the_jpeg_fast_path_matches_a_full_decode() {
+        let source = SourceImage::open(
+            &PathBuf::from("src/tests/Landscape_5.jpg"),
+            &DecodeLimits::default(),
+        )
+        .unwrap();
+        let (width, height) = (180, 120);
+
+        let fast = source.decode_for_thumbnail(width, height).unwrap();
+        let full = source.decode().unwrap();
+
+        // The fast path decodes a smaller image, but it has the same
+        // orientation as the full decode, and is still big enough.
+        assert_eq!(fast.dimensions(), (225, 150));
+        assert_eq!(full.dimensions(), (1800, 1200));
+
+        let fast = fast
+            .resize_exact(width, height, FilterType::Lanczos3)
+            .to_rgb8();
+        let full = full
+            .resize_exact(width, height, FilterType::Lanczos3)
+            .to_rgb8();
+
+        let total_difference: u64 = fast
+            .as_raw()
+            .iter()
+            .zip(full.as_raw())
+            .map(|(a, b)| a.abs_diff(*b) as u64)
+            .sum();
+        let mean_difference = total_difference / fast.as_raw().len() as u64;
+
+        assert!(mean_difference < 4, "{mean_difference}");
+    }
+
+    #[test]
+    fn other_formats_are_decoded_at_full_size() {
+        let source = SourceImage::open(
+            &PathBuf::from("src/tests/red.png"),
+            &DecodeLimits::default(),
+        )
+        .unwrap();
+
+        let img = source.decode_for_thumbnail(10, 20).unwrap();
+
+        assert_eq!(img.dimensions(), (100, 200));
+    }
+
+    #[test]
+    fn it_uses_an_embedded_preview_which_is_big_enough() {
+        let source = SourceImage::open(
+            &PathBuf::from("src/tests/embedded_preview.jpg"),
+            &DecodeLimits::default(),
+        )
+        .unwrap();
+        assert_eq!(source.dimensions, (480, 640));
+
+        // The preview is stored as 160×120, with the same orientation
+        // as the main image.
+        let preview = source.decode_embedded_preview(90, 120).unwrap();
+        assert_eq!(preview.dimensions(), (120, 160));
+    }
+
+    #[test]
+    fn it_skips_an_embedded_preview_which_is_too_small() {
+        let source = SourceImage::open(
+            &PathBuf::from("src/tests/embedded_preview.jpg"),
+            &DecodeLimits::default(),
+        )
+        .unwrap();
+
+        assert!(source.decode_embedded_preview(240, 320).is_none());
+    }
+
+    #[test]
+    fn it_rejects_an_image_with_too_many_pixels() {
+        let limits = DecodeLimits {
+            max_pixels: Some(10_000),
+            ..Default::default()
+        };
+
+        let result = SourceImage::open(&PathBuf::from("src/tests/red.png"), &limits);
+
+        assert!(matches!(
+            result,
+            Err(ThumbnailError::LimitsExceeded {
+                limit: LimitExceeded::Pixels {
+                    pixels: 20_000,
+                    max: 10_000
+                },
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn it_rejects_a_file_which_is_too_big() {
+        let limits = DecodeLimits {
+            max_input_bytes: Some(1000),
+            ..Default::default()
+        };
+
+        let result = SourceImage::open(&PathBuf::from("src/tests/red.png"), &limits);
+
+        assert!(matches!(
+            result,
+            Err(ThumbnailError::LimitsExceeded {
+                limit: LimitExceeded::InputBytes {
+                    bytes: 1835,
+                    max: 1000
+                },
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn it_rejects_a_decompression_bomb_by_default() {
+        let result = SourceImage::open(
+            &PathBuf::from("src/tests/bomb.png"),
+            &DecodeLimits::default(),
+        );
+
+        assert!(matches!(
+            result,
+            Err(ThumbnailError::LimitsExceeded {
+                limit: LimitExceeded::Alloc {
+                    bytes: 10_000_000_000,
+                    ..
+                },
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn it_rejects_a_gif_with_too_many_frames() {
+        let limits = DecodeLimits {
+            max_frames: Some(1),
+            ..Default::default()
+        };
+
+        let result = SourceImage::open(&PathBuf::from("src/tests/animated_squares.gif"), &limits);
+
+        assert!(matches!(
+            result,
+            Err(ThumbnailError::LimitsExceeded {
+                limit: LimitExceeded::Frames { max: 1, .. },
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn frame_limits_do_not_apply_to_static_gifs() {
+        let limits = DecodeLimits {
+            max_frames: Some(0),
+            ..Default::default()
+        };
+
+        assert!(SourceImage::open(&PathBuf::from("src/tests/static.gif"), &limits).is_ok());
+    }
+
+    #[test]
+    fn it_detects_the_format_from_the_contents_of_the_file() {
+        let out_dir = test_dir();
+        fs::create_dir_all(&out_dir).unwrap();
+
+        for name in ["red.jpg", "red"] {
+            let path = out_dir.join(name);
+            fs::copy("src/tests/red.png", &path).unwrap();
+
+            let source = SourceImage::open(&path, &DecodeLimits::default()).unwrap();
+
+            assert_eq!(source.format, ImageFormat::Png, "{name}");
+            assert_eq!(source.dimensions, (100, 200), "{name}");
+        }
+    }
+
+    #[test]
+    fn it_reads_an_image_from_a_stream() {
+        let bytes = fs::read("src/tests/red.png").unwrap();
+
+        let source = SourceImage::from_reader(bytes.as_slice(), &DecodeLimits::default()).unwrap();
+
+        assert_eq!(source.format, ImageFormat::Png);
+        assert_eq!(source.dimensions, (100, 200));
+        assert_eq!(source.bytes(), bytes);
+    }
+
+    #[test]
+    fn it_stops_reading_a_stream_which_is_too_big() {
+        let limits = DecodeLimits {
+            max_input_bytes: Some(100),
+            ..Default::default()
+        };
+
+        let result = SourceImage::from_reader(std::io::repeat(0), &limits);
+
+        assert!(matches!(
+            result,
+            Err(ThumbnailError::LimitsExceeded {
+                limit: LimitExceeded::InputBytes {
+                    bytes: 101,
+                    max: 100
+                },
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn it_fails_if_a_stream_is_not_a_recognisable_image() {
+        let result = SourceImage::from_reader(b"hello world".as_slice(), &DecodeLimits::default());
+
+        assert!(matches!(
+            result,
+            Err(ThumbnailError::UnsupportedFormat { .. })
+        ));
+    }
+}
@@ -0,0 +1,58 @@
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use image::GenericImageView;
+
+/// Return a path to a temporary directory to use for testing.
+///
+/// This function does *not* create the directory, just the path.
+pub fn test_dir() -> PathBuf {
+    let tmp_dir = tempfile::tempdir().unwrap();
+
+    tmp_dir.path().to_owned()
+}
+
+/// Return the dimensions for an image.
+pub fn get_dimensions(path: &PathBuf) -> (u32, u32) {
+    let img = image::open(path).unwrap();
+
+    img.dimensions()
+}
+
+/// Returns true if ffmpeg and ffprobe are installed.
+///
+/// Tests which need to run the real ffmpeg skip themselves if it isn't.
+pub fn ffmpeg_is_installed() -> bool {
+    ["ffmpeg", "ffprobe"].into_iter().all(|program| {
+        Command::new(program)
+            .arg("-version")
+            .output()
+            .is_ok_and(|output| output.status.success())
+    })
+}
+
+/// Return the number of frames in a video, and how long it lasts in seconds.
+pub fn get_video_length(path: &Path) -> (u64, f64) {
+    let output = Command::new("ffprobe")
+        .args(["-v", "error", "-count_frames", "-select_streams", "v:0"])
+        .args(["-show_entries", "stream=nb_read_frames:format=duration"])
+        .args(["-of", "default=noprint_wrappers=1"])
+        .arg(path)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "ffprobe failed on {:?}", path);
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let value = |key: &str| {
+        stdout
+            .lines()
+            .find_map(|line| line.strip_prefix(key)?.strip_prefix('='))
+            .unwrap()
+            .to_string()
+    };
+
+    (
+        value("nb_read_frames").parse().unwrap(),
+        value("duration").parse().unwrap(),
+    )
+}
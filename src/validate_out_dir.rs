@@ -0,0 +1,193 @@
+use std::io;
+use std::path::Path;
+
+use crate::errors::ThumbnailError;
+
+/// How much free space we want in the output directory, in bytes.
+///
+/// Thumbnails are small, so this is plenty -- it's only meant to catch
+/// a disk which is already full, before we spend time decoding an image.
+const MIN_FREE_SPACE: u64 = 1024 * 1024;
+
+/// Check that we'll be able to save thumbnails in `out_dir`, before we
+/// do any expensive work like decoding an image.
+///
+/// The directory doesn't have to exist yet, as long as we can create it:
+/// we check the closest part of the path which does exist.  That has to be
+/// a directory (not a file), we have to be able to write to it, and the
+/// disk it's on can't be full.
+///
+/// This doesn't create anything.
+pub fn validate_out_dir(out_dir: &Path) -> Result<(), ThumbnailError> {
+    let existing = out_dir
+        .ancestors()
+        .map(|p| {
+            if p.as_os_str().is_empty() {
+                Path::new(".")
+            } else {
+                p
+            }
+        })
+        .find(|p| p.exists())
+        .unwrap_or(Path::new("."));
+
+    if !existing.is_dir() {
+        return Err(ThumbnailError::OutDirNotADirectory {
+            path: existing.to_path_buf(),
+        });
+    }
+
+    check_writable(existing)?;
+    check_free_space(existing)?;
+
+    Ok(())
+}
+
+/// Check we can create files in the directory `dir`.
+#[cfg(unix)]
+fn check_writable(dir: &Path) -> Result<(), ThumbnailError> {
+    let c_path = c_path(dir)?;
+
+    // SAFETY: `c_path` is a valid, nul-terminated string.
+    if unsafe { libc::access(c_path.as_ptr(), libc::W_OK | libc::X_OK) } == 0 {
+        Ok(())
+    } else {
+        Err(ThumbnailError::io(dir, io::Error::last_os_error()))
+    }
+}
+
+/// Check we can create files in the directory `dir`.
+#[cfg(not(unix))]
+fn check_writable(dir: &Path) -> Result<(), ThumbnailError> {
+    let metadata = std::fs::metadata(dir).map_err(|e| ThumbnailError::io(dir, e))?;
+
+    if metadata.permissions().readonly() {
+        Err(ThumbnailError::io(
+            dir,
+            io::Error::from(io::ErrorKind::PermissionDenied),
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+/// Check the disk which holds `dir` isn't full.
+#[cfg(unix)]
+fn check_free_space(dir: &Path) -> Result<(), ThumbnailError> {
+    let c_path = c_path(dir)?;
+
+    // SAFETY: `c_path` is a valid, nul-terminated string, and `statvfs`
+    // only writes to the struct we pass it.
+    let mut stats: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(c_path.as_ptr(), &mut stats) } != 0 {
+        return Err(ThumbnailError::io(dir, io::Error::last_os_error()));
+    }
+
+    // The types of these fields vary between platforms.
+    #[allow(clippy::unnecessary_cast)]
+    let available = stats.f_bavail as u64 * stats.f_frsize as u64;
+
+    if available < MIN_FREE_SPACE {
+        return Err(ThumbnailError::DiskFull {
+            path: dir.to_path_buf(),
+            source: io::Error::new(
+                io::ErrorKind::StorageFull,
+                format!("only {} bytes are available", available),
+            ),
+        });
+    }
+
+    Ok(())
+}
+
+/// Check the disk which holds `dir` isn't full.
+///
+/// We don't know how to check this on other platforms, so we find out
+/// when we try to write the thumbnail.
+#[cfg(not(unix))]
+fn check_free_space(_: &Path) -> Result<(), ThumbnailError> {
+    Ok(())
+}
+
+/// Convert a path to a string we can pass to libc.
+///
+/// This fails if the path contains a nul byte, which can't be part of
+/// a real path on Unix anyway.
+#[cfg(unix)]
+fn c_path(path: &Path) -> Result<std::ffi::CString, ThumbnailError> {
+    use std::os::unix::ffi::OsStrExt;
+
+    std::ffi::CString::new(path.as_os_str().as_bytes()).map_err(|_| {
+        ThumbnailError::InvalidOptions(format!(
+            "{} is not a valid path: it contains a nul byte",
+            path.display()
+        ))
+    })
+}
+
+#[cfg(test)]
+mod test_validate_out_dir {
+    use std::fs;
+
+    use super::*;
+    use crate::test_utils::test_dir;
+
+    #[test]
+    fn it_accepts_an_existing_directory() {
+        let dir = test_dir();
+        fs::create_dir_all(&dir).unwrap();
+
+        assert!(validate_out_dir(&dir).is_ok());
+        assert!(validate_out_dir(Path::new("")).is_ok());
+    }
+
+    #[test]
+    fn it_accepts_a_directory_which_doesnt_exist_yet() {
+        let dir = test_dir().join("path/to/thumbnails");
+
+        assert!(validate_out_dir(&dir).is_ok());
+        assert!(!dir.exists());
+    }
+
+    #[test]
+    fn it_rejects_a_file() {
+        let result = validate_out_dir(Path::new("README.md"));
+
+        assert!(matches!(
+            result,
+            Err(ThumbnailError::OutDirNotADirectory { path }) if path == Path::new("README.md")
+        ));
+    }
+
+    #[test]
+    fn it_rejects_a_directory_inside_a_file() {
+        let result = validate_out_dir(Path::new("README.md/thumbnails"));
+
+        assert!(matches!(
+            result,
+            Err(ThumbnailError::OutDirNotADirectory { path }) if path == Path::new("README.md")
+        ));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn it_rejects_a_directory_we_cant_write_to() {
+        use std::os::unix::fs::PermissionsExt;
+
+        // Permissions don't apply to root, so there's nothing to test.
+        if unsafe { libc::geteuid() } == 0 {
+            return;
+        }
+
+        let dir = test_dir();
+        fs::create_dir_all(&dir).unwrap();
+        fs::set_permissions(&dir, fs::Permissions::from_mode(0o555)).unwrap();
+
+        let result = validate_out_dir(&dir.join("thumbnails"));
+
+        assert!(matches!(
+            result,
+            Err(ThumbnailError::PermissionDenied { path, .. }) if path == dir
+        ));
+    }
+}
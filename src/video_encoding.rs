@@ -0,0 +1,361 @@
+use clap::ValueEnum;
+use serde::Serialize;
+
+use crate::colour::Colour;
+
+/// The video codecs we can use when ffmpeg creates a looping thumbnail.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum VideoCodec {
+    /// H.264 in an MP4 container, which plays almost everywhere
+    #[default]
+    H264,
+
+    /// VP9 in a WebM container
+    Vp9,
+
+    /// AV1 in an MP4 container, which is smallest but slowest to encode
+    Av1,
+}
+
+/// What to do if a video thumbnail would have an odd width or height.
+///
+/// The pixel format we use (yuv420p) stores colour at half resolution,
+/// so ffmpeg can only create videos whose width and height are even.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum EvenDimensions {
+    /// Shrink the thumbnail slightly, so both dimensions are even and
+    /// the aspect ratio is as close as possible to the original
+    #[default]
+    Fit,
+
+    /// Add a one-pixel border of the background colour to the right
+    /// and/or bottom edges
+    Pad,
+
+    /// Remove one pixel from the right and/or bottom edges
+    Crop,
+}
+
+/// Settings for the looping videos we create with ffmpeg, i.e. thumbnails
+/// of animated GIFs and preview clips of videos.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct VideoEncoding {
+    pub codec: VideoCodec,
+
+    /// Constant Rate Factor -- lower values are higher quality, but
+    /// create bigger files.  If this is `None`, we use a default that
+    /// suits the codec.
+    pub crf: Option<u32>,
+
+    /// The maximum frame rate of the output video
+    pub max_fps: Option<u32>,
+
+    /// The maximum length of the output video, in seconds
+    pub max_duration: Option<f64>,
+
+    /// How to make sure the video has even dimensions
+    pub even_dimensions: EvenDimensions,
+}
+
+impl VideoEncoding {
+    /// The file extension for videos created with these settings.
+    pub fn extension(&self) -> &'static str {
+        match self.codec {
+            VideoCodec::H264 | VideoCodec::Av1 => "mp4",
+            VideoCodec::Vp9 => "webm",
+        }
+    }
+
+    /// The CRF we pass to ffmpeg.
+    ///
+    /// The defaults are the ffmpeg defaults for H.264, and the values
+    /// suggested in the ffmpeg wiki for VP9 and AV1.  The scales are
+    /// different for each codec, so these are roughly equivalent.
+    pub fn crf(&self) -> u32 {
+        match (self.crf, self.codec) {
+            (Some(crf), _) => crf,
+            (None, VideoCodec::H264) => 23,
+            (None, VideoCodec::Vp9) => 33,
+            (None, VideoCodec::Av1) => 35,
+        }
+    }
+
+    /// The ffmpeg output arguments for these settings.
+    ///
+    /// These go after the input file and before the output file.
+    pub fn ffmpeg_args(&self) -> Vec<String> {
+        let mut args: Vec<String> = match self.codec {
+            VideoCodec::H264 => vec!["-c:v", "libx264", "-movflags", "faststart"],
+
+            // With libvpx and libaom, you need `-b:v 0` for the CRF to
+            // be used as a constant quality target.
+            VideoCodec::Vp9 => vec!["-c:v", "libvpx-vp9", "-b:v", "0"],
+            VideoCodec::Av1 => vec!["-c:v", "libaom-av1", "-b:v", "0", "-movflags", "faststart"],
+        }
+        .into_iter()
+        .map(String::from)
+        .collect();
+
+        args.extend([
+            "-crf".to_string(),
+            self.crf().to_string(),
+            "-pix_fmt".to_string(),
+            "yuv420p".to_string(),
+            "-an".to_string(),
+        ]);
+
+        if let Some(max_fps) = self.max_fps {
+            args.extend(["-fpsmax".to_string(), max_fps.to_string()]);
+        }
+
+        if let Some(max_duration) = self.max_duration {
+            args.extend(["-t".to_string(), max_duration.to_string()]);
+        }
+
+        args
+    }
+
+    /// The extra ffmpeg output arguments we need to write a video to a pipe,
+    /// rather than a file.
+    ///
+    /// ffmpeg can't guess the container from the name of a pipe, and
+    /// a normal MP4 has to go back and write an index at the start of
+    /// the file, which it can't do on a pipe.  A fragmented MP4 can be
+    /// written in one pass.  These come after `ffmpeg_args()`, so they
+    /// override the `-movflags` in there.
+    pub fn pipe_args(&self) -> Vec<String> {
+        let args = match self.extension() {
+            "mp4" => vec!["-f", "mp4", "-movflags", "frag_keyframe+empty_moov"],
+            other => vec!["-f", other],
+        };
+
+        args.into_iter().map(String::from).collect()
+    }
+
+    /// The ffmpeg video filter that resizes a video to `width`×`height`,
+    /// adjusted to have even dimensions, and the dimensions of the result.
+    ///
+    /// Only the `pad` policy can make the video bigger than `width`×`height`,
+    /// and it fills the extra pixels with `background`.
+    pub fn scale_filter(
+        &self,
+        width: u32,
+        height: u32,
+        background: Colour,
+    ) -> (String, (u32, u32)) {
+        match self.even_dimensions {
+            EvenDimensions::Fit => {
+                let (w, h) = fit_even(width, height);
+                (format!("scale={}:{}", w, h), (w, h))
+            }
+            EvenDimensions::Pad => {
+                let (w, h) = (round_up_even(width), round_up_even(height));
+                let filter = format!(
+                    "scale={}:{},pad={}:{}:0:0:color={}",
+                    width,
+                    height,
+                    w,
+                    h,
+                    background.to_ffmpeg()
+                );
+                (filter, (w, h))
+            }
+            EvenDimensions::Crop => {
+                // We can't crop a 1-pixel dimension down to 0, so in that
+                // case we scale to 2 pixels instead.
+                let (width, height) = (width.max(2), height.max(2));
+                let (w, h) = (round_down_even(width), round_down_even(height));
+                let filter = format!("scale={}:{},crop={}:{}:0:0", width, height, w, h);
+                (filter, (w, h))
+            }
+        }
+    }
+}
+
+/// Round up to the nearest even number.
+fn round_up_even(x: u32) -> u32 {
+    x + x % 2
+}
+
+/// Round down to the nearest even number, but never below 2.
+fn round_down_even(x: u32) -> u32 {
+    (x - x % 2).max(2)
+}
+
+/// Round to the closest even number, but never below 2.
+fn nearest_even(x: f64) -> u32 {
+    (((x / 2.0).round() * 2.0) as u32).max(2)
+}
+
+/// Find even dimensions which fit inside `width`×`height` and have
+/// roughly the same aspect ratio.
+///
+/// We try shrinking the width to an even number and scaling the height to
+/// match; if that height is too tall, we go the other way round.
+fn fit_even(width: u32, height: u32) -> (u32, u32) {
+    let aspect_ratio = (width as f64) / (height as f64);
+
+    let w = round_down_even(width);
+    let h = nearest_even((w as f64) / aspect_ratio);
+
+    if h <= height.max(2) {
+        return (w, h);
+    }
+
+    let h = round_down_even(height);
+    let w = nearest_even((h as f64) * aspect_ratio).min(w);
+
+    (w, h)
+}
+
+#[cfg(test)]
+mod test_video_encoding {
+    use super::*;
+
+    macro_rules! fit_even_tests {
+        ($($name:ident: $value:expr,)*) => {
+        $(
+            #[test]
+            fn $name() {
+                let (input, expected) = $value;
+
+                assert_eq!(fit_even(input.0, input.1), expected);
+            }
+        )*
+        }
+    }
+
+    fit_even_tests! {
+        fit_already_even:  ((16, 32), (16, 32)),
+        fit_odd_square:    ((15, 15), (14, 14)),
+        fit_odd_width:     ((15, 20), (14, 18)),
+        fit_odd_height:    ((20, 15), (18, 14)),
+        fit_wide:          ((101, 3), (100, 2)),
+        fit_tall:          ((3, 101), (2, 68)),
+        fit_tiny:          ((1, 1), (2, 2)),
+    }
+
+    #[test]
+    fn fit_never_exceeds_the_original_dimensions() {
+        for width in 2..60 {
+            for height in 2..60 {
+                let (w, h) = fit_even(width, height);
+                assert!(w <= width && h <= height, "{width}x{height} -> {w}x{h}");
+                assert!(w % 2 == 0 && h % 2 == 0, "{width}x{height} -> {w}x{h}");
+            }
+        }
+    }
+
+    #[test]
+    fn it_pads_to_even_dimensions() {
+        let encoding = VideoEncoding {
+            even_dimensions: EvenDimensions::Pad,
+            ..Default::default()
+        };
+
+        let background = Colour {
+            red: 255,
+            green: 255,
+            blue: 255,
+        };
+
+        assert_eq!(
+            encoding.scale_filter(15, 20, background),
+            (
+                "scale=15:20,pad=16:20:0:0:color=0xffffff".to_string(),
+                (16, 20)
+            )
+        );
+    }
+
+    #[test]
+    fn it_crops_to_even_dimensions() {
+        let encoding = VideoEncoding {
+            even_dimensions: EvenDimensions::Crop,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            encoding.scale_filter(15, 20, Colour::BLACK),
+            ("scale=15:20,crop=14:20:0:0".to_string(), (14, 20))
+        );
+    }
+
+    #[test]
+    fn it_fits_to_even_dimensions_by_default() {
+        let encoding = VideoEncoding::default();
+
+        assert_eq!(
+            encoding.scale_filter(15, 15, Colour::BLACK),
+            ("scale=14:14".to_string(), (14, 14))
+        );
+    }
+
+    #[test]
+    fn the_default_is_h264_in_an_mp4() {
+        let encoding = VideoEncoding::default();
+
+        assert_eq!(encoding.extension(), "mp4");
+        assert_eq!(
+            encoding.ffmpeg_args(),
+            vec![
+                "-c:v",
+                "libx264",
+                "-movflags",
+                "faststart",
+                "-crf",
+                "23",
+                "-pix_fmt",
+                "yuv420p",
+                "-an"
+            ]
+        );
+    }
+
+    #[test]
+    fn vp9_is_in_a_webm() {
+        let encoding = VideoEncoding {
+            codec: VideoCodec::Vp9,
+            ..Default::default()
+        };
+
+        assert_eq!(encoding.extension(), "webm");
+        assert!(encoding.ffmpeg_args().starts_with(&[
+            "-c:v".to_string(),
+            "libvpx-vp9".to_string(),
+            "-b:v".to_string(),
+            "0".to_string(),
+        ]));
+    }
+
+    #[test]
+    fn it_uses_a_custom_crf() {
+        let encoding = VideoEncoding {
+            codec: VideoCodec::Av1,
+            crf: Some(40),
+            ..Default::default()
+        };
+
+        assert_eq!(encoding.crf(), 40);
+        assert!(encoding.ffmpeg_args().contains(&"40".to_string()));
+    }
+
+    #[test]
+    fn it_limits_the_frame_rate_and_duration() {
+        let encoding = VideoEncoding {
+            max_fps: Some(12),
+            max_duration: Some(2.5),
+            ..Default::default()
+        };
+
+        let args = encoding.ffmpeg_args();
+        assert!(args.ends_with(&[
+            "-fpsmax".to_string(),
+            "12".to_string(),
+            "-t".to_string(),
+            "2.5".to_string(),
+        ]));
+    }
+}